@@ -4,27 +4,49 @@ use clap::{Arg, App};
 use std::str::FromStr;
 
 extern crate k_tune;
-use k_tune::gemm;
+use k_tune::{gemm, values};
+use k_tune::device::{self, PlatformSelector, DeviceSelector};
+use k_tune::multi::MultiTuner;
 
-pub fn gemm(platform_id: usize, device_id: usize, m: usize, n: usize, k: usize,
-            file: Option<&str>, runs: usize)
+pub fn gemm(platform: &PlatformSelector, devices: &[DeviceSelector], m: usize, n: usize,
+            k: usize, file: Option<&str>, runs: usize)
             -> ::ocl::Result<()> {
-    println!("Platform: {}\nDevice: {}\nM: {}\nN: {}\nK: {}\n", platform_id, device_id, m, n, k);
+    println!("Platform: {:?}\nDevices: {:?}\nM: {}\nN: {}\nK: {}\n", platform, devices, m, n, k);
     let params = gemm::GemmBuilder::default()
-        .mwg(vec![256, 128, 64, 32])
-        .nwg(vec![256, 128, 64, 32])
-        .kwg(vec![32, 16, 8])
+        .mwg(values::powers_of_two(32, 256))
+        .nwg(values::powers_of_two(32, 256))
+        .kwg(values::divisors(k, 8, 32))
         .mdimc(vec![32, 16, 8])
         .ndimc(vec![32, 16, 8])
         .mdima(vec![32, 16, 8])
         .ndimb(vec![32, 16, 8])
-        .kwi(vec![16, 8, 4, 2])
+        .kwi(values::powers_of_two(2, 16))
         .precision(vec![32])
         .build()
         .unwrap();
     let wrapper = gemm::build_kernel_wrapper("templates/gemm.ocl", m, n, k);
-    let tuner = k_tune::Tuner::new(platform_id, device_id);
-    tuner.tune(wrapper, params, runs, file);
+    if devices.len() == 1 {
+        let tuner = k_tune::Tuner::select(platform, &devices[0]).unwrap();
+        tuner.tune(wrapper, params, runs, file).unwrap();
+    } else {
+        let tuners = devices
+            .iter()
+            .map(|d| k_tune::Tuner::select(platform, d).unwrap())
+            .collect();
+        let results = MultiTuner::new(tuners).tune(wrapper, params, runs, file).unwrap();
+        for results in results {
+            match results.best() {
+                Some(best) => {
+                    println!("{}: best time {}.{:09}s with {:?}",
+                             results.fingerprint.device,
+                             best.time.as_secs(),
+                             best.time.subsec_nanos(),
+                             best.parameters)
+                }
+                None => println!("{}: no valid configuration.", results.fingerprint.device),
+            }
+        }
+    }
     Ok(())
 }
 
@@ -44,18 +66,28 @@ fn main() {
                  .takes_value(true)
                  .default_value("10")
                  .help("The log file to which to write results."))
+        .arg(Arg::with_name("list")
+                 .short("l")
+                 .long("list")
+                 .help("Lists all OpenCL platforms and devices and exits."))
         .arg(Arg::with_name("platform")
                  .short("p")
                  .long("platform")
                  .takes_value(true)
-                 .default_value("0")
-                 .help("Sets the OpenCL platform to use."))
+                 .default_value("any")
+                 .help("Sets the OpenCL platform to use: 'any', an index, 'name:<substring>' \
+                        or 'vendor:<substring>'. With 'any', the first platform with a \
+                        matching device is used."))
         .arg(Arg::with_name("device")
                  .short("d")
                  .long("device")
                  .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1)
                  .default_value("0")
-                 .help("Sets the OpenCL device to use."))
+                 .help("Sets the OpenCL device to use: an index, 'gpu', 'cpu', 'accelerator', \
+                        'name:<substring>' or 'vendor:<substring>'. Can be given several times \
+                        to tune on multiple devices in parallel."))
         .arg(Arg::with_name("m")
                  .short("m")
                  .long("m")
@@ -75,16 +107,21 @@ fn main() {
                  .default_value("2048")
                  .help("The second dimension of the matrix A and first dimension of B."))
         .get_matches();
-    let p_id =
-        usize::from_str(matches.value_of("platform").unwrap()).expect("Platform must be a valid \
-                                                                       integer.");
-    let d_id =
-        usize::from_str(matches.value_of("device").unwrap()).expect("Device must be a valid \
-                                                                     integer.");
+    if matches.is_present("list") {
+        device::print_devices();
+        return;
+    }
+    let platform = PlatformSelector::from_str(matches.value_of("platform").unwrap())
+        .expect("Platform must be a valid selector.");
+    let devices: Vec<DeviceSelector> = matches
+        .values_of("device")
+        .unwrap()
+        .map(|d| DeviceSelector::from_str(d).expect("Device must be a valid selector."))
+        .collect();
     let m = usize::from_str(matches.value_of("m").unwrap()).expect("m must be a valid integer.");
     let n = usize::from_str(matches.value_of("n").unwrap()).expect("n must be a valid integer.");
     let k = usize::from_str(matches.value_of("k").unwrap()).expect("k must be a valid integer.");
     let file = matches.value_of("file");
     let runs = usize::from_str(matches.value_of("runs").unwrap()).unwrap();
-    gemm(p_id, d_id, m, n, k, file, runs).unwrap();
+    gemm(&platform, &devices, m, n, k, file, runs).unwrap();
 }
@@ -11,7 +11,7 @@ pub fn simple() -> ::ocl::Result<()> {
         .unwrap();
     let wrapper = simple::build_kernel_wrapper("templates/simple.ocl", 1024, 1024);
     let tuner = k_tune::Tuner::default();
-    tuner.tune(wrapper, params, 10, None);
+    tuner.tune(wrapper, params, 10, None).unwrap();
     Ok(())
 }
 
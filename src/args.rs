@@ -0,0 +1,233 @@
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use ocl::{Buffer, Kernel, Sampler};
+use ocl::aliases::{ClFloat2, ClFloat4, ClInt2, ClInt4};
+
+use core::{Config, FnWrap, Formula};
+use expr;
+use image::{ImageData, SamplerArg};
+
+/// The name of the `n`th null buffer argument of a kernel. ocl only sets a null buffer through
+/// a named argument, whose name must be `'static`. The names are interned: each one is leaked
+/// the first time a kernel has that many null arguments and reused by every later kernel, so
+/// the memory is bounded by the most null arguments of any kernel, not by the number of
+/// configurations.
+fn null_name(n: usize) -> &'static str {
+    static NAMES: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+    let mut names = NAMES.lock().unwrap_or_else(|e| e.into_inner());
+    while names.len() <= n {
+        let name = format!("null_{}", names.len());
+        names.push(Box::leak(name.into_boxed_str()));
+    }
+    names[n]
+}
+
+/// A kernel argument, see `KernelWrapper::args`.
+#[derive(Clone, Debug)]
+pub enum Arg {
+    /// The buffer at this index of `KernelWrapper::inputs_dims`.
+    Buffer(usize),
+    /// The size of a dimension of a buffer as an `int`, including the padding for the
+    /// configuration, e.g. the leading dimension of a padded matrix.
+    Dim(usize, usize),
+    Scalar(Scalar),
+    /// A `__local` memory argument with the number of bytes computed from the configuration.
+    /// It counts toward the local memory limit together with `local_memory_needed`.
+    Local(Formula),
+    /// A null `__global` pointer.
+    Null,
+    /// The image at this index of `KernelWrapper::images`.
+    Image(usize),
+    Sampler(SamplerArg),
+}
+
+/// A scalar kernel argument of any OpenCL type.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scalar {
+    Char(i8),
+    UChar(u8),
+    Short(i16),
+    UShort(u16),
+    Int(i32),
+    UInt(u32),
+    Long(i64),
+    ULong(u64),
+    Float(f32),
+    Double(f64),
+    Int2([i32; 2]),
+    Int4([i32; 4]),
+    Float2([f32; 2]),
+    Float4([f32; 4]),
+}
+
+impl Scalar {
+    /// Parses a value of the named OpenCL type, with the components of vector types separated
+    /// by commas, e.g. `parse("float4", "1, 0, 0, 1")`.
+    pub fn parse(data_type: &str, value: &str) -> Result<Self, String> {
+        fn num<T: FromStr>(value: &str) -> Result<T, String> {
+            T::from_str(value.trim()).map_err(|_| format!("Invalid number '{}'.", value))
+        }
+        fn components<T>(value: &str, n: usize) -> Result<[T; 4], String>
+            where T: FromStr + Copy + Default
+        {
+            let parts: Vec<&str> = value.split(',').collect();
+            if parts.len() != n {
+                return Err(format!("Expected {} components, got '{}'.", n, value));
+            }
+            let mut result = [T::default(); 4];
+            for (i, part) in parts.iter().enumerate() {
+                result[i] = num(part)?;
+            }
+            Ok(result)
+        }
+        match data_type {
+            "char" => Ok(Scalar::Char(num(value)?)),
+            "uchar" => Ok(Scalar::UChar(num(value)?)),
+            "short" => Ok(Scalar::Short(num(value)?)),
+            "ushort" => Ok(Scalar::UShort(num(value)?)),
+            "int" => Ok(Scalar::Int(num(value)?)),
+            "uint" => Ok(Scalar::UInt(num(value)?)),
+            "long" => Ok(Scalar::Long(num(value)?)),
+            "ulong" => Ok(Scalar::ULong(num(value)?)),
+            "float" => Ok(Scalar::Float(num(value)?)),
+            "double" => Ok(Scalar::Double(num(value)?)),
+            "int2" => components(value, 2).map(|x| Scalar::Int2([x[0], x[1]])),
+            "int4" => components(value, 4).map(Scalar::Int4),
+            "float2" => components(value, 2).map(|x| Scalar::Float2([x[0], x[1]])),
+            "float4" => components(value, 4).map(Scalar::Float4),
+            _ => Err(format!("Unknown scalar type '{}'.", data_type)),
+        }
+    }
+
+    fn set(&self, kernel: Kernel) -> Kernel {
+        match *self {
+            Scalar::Char(x) => kernel.arg_scl(x),
+            Scalar::UChar(x) => kernel.arg_scl(x),
+            Scalar::Short(x) => kernel.arg_scl(x),
+            Scalar::UShort(x) => kernel.arg_scl(x),
+            Scalar::Int(x) => kernel.arg_scl(x),
+            Scalar::UInt(x) => kernel.arg_scl(x),
+            Scalar::Long(x) => kernel.arg_scl(x),
+            Scalar::ULong(x) => kernel.arg_scl(x),
+            Scalar::Float(x) => kernel.arg_scl(x),
+            Scalar::Double(x) => kernel.arg_scl(x),
+            Scalar::Int2(x) => kernel.arg_scl(ClInt2(x[0], x[1])),
+            Scalar::Int4(x) => kernel.arg_scl(ClInt4(x[0], x[1], x[2], x[3])),
+            Scalar::Float2(x) => kernel.arg_scl(ClFloat2(x[0], x[1])),
+            Scalar::Float4(x) => kernel.arg_scl(ClFloat4(x[0], x[1], x[2], x[3])),
+        }
+    }
+}
+
+impl Arg {
+    /// A `__local` argument of `count` elements of `element_size` bytes, with `count` an
+    /// expression over the parameters, e.g. `Arg::local("WGS * WPT", 4, &names)`.
+    pub fn local(count: &str, element_size: i32, names: &[&str]) -> Result<Arg, String> {
+        let count = expr::formula(count, names)?;
+        let args = count.args().to_vec();
+        Ok(Arg::Local(FnWrap::new(move |config: &Config| {
+                                      count
+                                          .call(config)?
+                                          .checked_mul(element_size)
+                                          .ok_or_else(|| "Overflow in local size.".to_string())
+                                  })
+                              .uses(args)))
+    }
+
+    /// The bytes of a `__local` argument for the configuration, or 0 for other arguments.
+    pub fn local_bytes(&self, config: &Config) -> Result<u64, String> {
+        match *self {
+            Arg::Local(ref bytes) => {
+                let n = bytes.call(config)?;
+                if n < 0 {
+                    return Err(format!("The local argument size {} is negative.", n));
+                }
+                Ok(n as u64)
+            }
+            _ => Ok(0),
+        }
+    }
+}
+
+/// Sets the arguments of the kernel in order, with one sampler for every `Arg::Sampler`.
+/// `dims` are the padded shapes of the buffers.
+pub fn set_args(mut kernel: Kernel, args: &[Arg], buffers: &[Buffer<f32>], dims: &[Vec<usize>],
+                images: &[ImageData], samplers: &[Sampler], config: &Config)
+                -> Result<Kernel, String> {
+    let mut nulls = 0;
+    let mut samplers = samplers.iter();
+    for arg in args {
+        kernel = match *arg {
+            Arg::Buffer(i) => {
+                let buffer = buffers
+                    .get(i)
+                    .ok_or_else(|| format!("Buffer {} does not exist.", i))?;
+                kernel.arg_buf(buffer)
+            }
+            Arg::Dim(i, d) => {
+                let size = dims.get(i)
+                    .and_then(|dims| dims.get(d))
+                    .ok_or_else(|| format!("Dimension {} of buffer {} does not exist.", d, i))?;
+                if *size > i32::MAX as usize {
+                    return Err(format!("Dimension {} of buffer {} does not fit in an int.", d, i));
+                }
+                kernel.arg_scl(*size as i32)
+            }
+            Arg::Scalar(ref scalar) => scalar.set(kernel),
+            Arg::Local(_) => kernel.arg_loc::<u8>(arg.local_bytes(config)? as usize),
+            Arg::Null => {
+                let name = null_name(nulls);
+                nulls += 1;
+                kernel.arg_buf_named::<f32, &Buffer<f32>>(name, None)
+            }
+            Arg::Image(i) => {
+                images
+                    .get(i)
+                    .ok_or_else(|| format!("Image {} does not exist.", i))?
+                    .set(kernel)
+            }
+            Arg::Sampler(_) => {
+                kernel.arg_smp(samplers.next().ok_or("Missing sampler.".to_string())?)
+            }
+        };
+    }
+    Ok(kernel)
+}
+
+#[cfg(test)]
+mod tests {
+    use core::Value;
+
+    use super::*;
+
+    #[test]
+    fn null_names_are_interned() {
+        assert_eq!(null_name(2), "null_2");
+        assert_eq!(null_name(0), "null_0");
+        assert!(::std::ptr::eq(null_name(2), null_name(2)));
+    }
+
+    #[test]
+    fn scalars_parse_every_type() {
+        assert_eq!(Scalar::parse("uchar", "255"), Ok(Scalar::UChar(255)));
+        assert_eq!(Scalar::parse("long", " -3 "), Ok(Scalar::Long(-3)));
+        assert_eq!(Scalar::parse("int2", "1,2"), Ok(Scalar::Int2([1, 2])));
+        assert_eq!(Scalar::parse("float4", "1, 0, 0, 1"),
+                   Ok(Scalar::Float4([1.0, 0.0, 0.0, 1.0])));
+        assert!(Scalar::parse("uchar", "256").is_err());
+        assert!(Scalar::parse("float2", "1, 2, 3").is_err());
+        assert!(Scalar::parse("half", "1").is_err());
+    }
+
+    #[test]
+    fn local_bytes() {
+        let arg = Arg::local("A * 2", 4, &["A"]).unwrap();
+        let mut config = Config::new();
+        config.insert("A".into(), Value::Int(8));
+        assert_eq!(arg.local_bytes(&config), Ok(64));
+        config.insert("A".into(), Value::Int(-8));
+        assert!(arg.local_bytes(&config).is_err());
+        assert_eq!(Arg::Null.local_bytes(&config), Ok(0));
+    }
+}
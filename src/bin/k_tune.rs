@@ -0,0 +1,348 @@
+extern crate clap;
+extern crate k_tune;
+
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+
+use clap::{Arg, App, ArgMatches, SubCommand};
+
+use k_tune::{Tuner, Strategy};
+use k_tune::device::{self, PlatformSelector, DeviceSelector};
+use k_tune::multi::MultiTuner;
+use k_tune::results::{self, LogFormat};
+use k_tune::spec::Spec;
+use k_tune::sweep::{self, Aggregate, SweepResults};
+
+fn devices() -> Result<(), String> {
+    device::print_devices();
+    Ok(())
+}
+
+fn tune(matches: &ArgMatches) -> Result<(), String> {
+    let file = matches.value_of("spec").unwrap();
+    let mut spec = Spec::from_file(file)?;
+    if let Some(sizes) = matches.values_of("size") {
+        for size in sizes {
+            let mut split = size.splitn(2, '=');
+            let name = split.next().unwrap();
+            let value = split
+                .next()
+                .and_then(|v| usize::from_str(v).ok())
+                .ok_or_else(|| format!("Size must be given as NAME=VALUE, got '{}'.", size))?;
+            spec.set_size(name, value)?;
+        }
+    }
+    let dir = Path::new(file).parent().unwrap_or(Path::new(""));
+
+    let platform = PlatformSelector::from_str(matches.value_of("platform").unwrap())?;
+    let devices = matches
+        .values_of("device")
+        .unwrap()
+        .map(DeviceSelector::from_str)
+        .collect::<Result<Vec<_>, _>>()?;
+    let runs = usize::from_str(matches.value_of("runs").unwrap())
+        .ok()
+        .filter(|&runs| runs > 0)
+        .ok_or_else(|| "Runs must be a positive integer.".to_string())?;
+    let strategy = Strategy::from_str(matches.value_of("strategy").unwrap())?;
+    let budget = match matches.value_of("budget") {
+        Some(b) => {
+            Some(usize::from_str(b).map_err(|_| "Budget must be a valid integer.".to_string())?)
+        }
+        None => None,
+    };
+    let peak_gflops = match matches.value_of("peak-gflops") {
+        Some(p) => {
+            Some(f64::from_str(p).map_err(|_| "Peak GFLOPS must be a valid number.".to_string())?)
+        }
+        None => None,
+    };
+    let peak_bandwidth = match matches.value_of("peak-bandwidth") {
+        Some(b) => {
+            Some(f64::from_str(b)
+                     .map_err(|_| "Peak bandwidth must be a valid number.".to_string())?)
+        }
+        None => None,
+    };
+    let log_format = LogFormat::from_str(matches.value_of("log-format").unwrap())?;
+    let verbose = !matches.is_present("quiet");
+    let mut tuners = Vec::new();
+    for device in &devices {
+        tuners.push(Tuner::select(&platform, device)?
+                        .strategy(strategy)
+                        .budget(budget)
+                        .peak_gflops(peak_gflops)
+                        .peak_bandwidth(peak_bandwidth)
+                        .log_format(log_format)
+                        .verbose(verbose));
+    }
+    let log_file = matches.value_of("log");
+
+    if let Some(sweeps) = matches.values_of("sweep") {
+        if tuners.len() != 1 {
+            return Err("Sweeps over problem sizes run on a single device.".into());
+        }
+        let mut grid = Vec::new();
+        for s in sweeps {
+            let mut split = s.splitn(2, '=');
+            let name = split.next().unwrap();
+            let values: Vec<usize> = split
+                .next()
+                .and_then(|v| v.split(',').map(|x| usize::from_str(x.trim()).ok()).collect())
+                .ok_or_else(|| format!("Sweeps must be given as NAME=V1,V2,..., got '{}'.", s))?;
+            grid.push((name, values));
+        }
+        let aggregate = Aggregate::from_str(matches.value_of("aggregate").unwrap())?;
+        let results = sweep::tune(&tuners[0],
+                                  &sweep::grid(&grid),
+                                  |size| {
+                                      let mut spec = spec.clone();
+                                      for &(ref name, value) in size {
+                                          spec.set_size(name, value)?;
+                                      }
+                                      spec.build(dir)
+                                  },
+                                  runs,
+                                  log_file)?;
+        results.print(aggregate);
+        if let Some(output) = matches.value_of("output") {
+            results::save(output, &results.results)?;
+        }
+        return Ok(());
+    }
+
+    let (wrapper, params) = spec.build(dir)?;
+    let all_results = if tuners.len() == 1 {
+        vec![tuners[0].tune(wrapper, params, runs, log_file)?]
+    } else {
+        MultiTuner::new(tuners).tune(wrapper, params, runs, log_file)?
+    };
+    for results in &all_results {
+        match results.best() {
+            Some(best) => {
+                println!("{}: best time {}.{:09}s with {:?}",
+                         results.fingerprint.device,
+                         best.time.as_secs(),
+                         best.time.subsec_nanos(),
+                         best.parameters)
+            }
+            None => println!("{}: no valid configuration.", results.fingerprint.device),
+        }
+    }
+    if let Some(output) = matches.value_of("output") {
+        results::save(output, &all_results)?;
+    }
+    Ok(())
+}
+
+fn show(matches: &ArgMatches) -> Result<(), String> {
+    let top = match matches.value_of("top") {
+        Some(n) => {
+            Some(usize::from_str(n).map_err(|_| "Top must be a valid integer.".to_string())?)
+        }
+        None => None,
+    };
+    for mut results in results::load(matches.value_of("results").unwrap())? {
+        match matches.value_of("sort").unwrap() {
+            "time" => results.sort_by_time(),
+            name => results.sort_by_parameter(name)?,
+        }
+        results.print(top);
+        println!();
+    }
+    Ok(())
+}
+
+fn export(matches: &ArgMatches) -> Result<(), String> {
+    for results in results::load(matches.value_of("results").unwrap())? {
+        let best = match results.best() {
+            Some(best) => best,
+            None => continue,
+        };
+        match matches.value_of("format").unwrap() {
+            "defines" => {
+                println!("// {} on {}", results.kernel, results.fingerprint.device);
+                print!("{}", best.defines());
+            }
+            "database" => println!("{}", results.database_entry().unwrap()),
+            format => return Err(format!("Unknown export format '{}'.", format)),
+        }
+    }
+    Ok(())
+}
+
+fn select(matches: &ArgMatches) -> Result<(), String> {
+    let results = SweepResults { results: results::load(matches.value_of("results").unwrap())? };
+    let k = usize::from_str(matches.value_of("count").unwrap())
+        .map_err(|_| "Count must be a valid integer.".to_string())?;
+    let tolerance = f64::from_str(matches.value_of("tolerance").unwrap())
+        .map_err(|_| "Tolerance must be a valid number.".to_string())?;
+    let selection = results.select(k, tolerance / 100.0);
+    selection.print();
+    println!();
+    print!("{}", selection.dispatch_table());
+    Ok(())
+}
+
+fn main() {
+    let matches = App::new("k_tune")
+        .version(results::VERSION)
+        .about("A kernel tuning tool for OpenCL")
+        .subcommand(SubCommand::with_name("devices")
+                        .about("Lists all OpenCL platforms and devices."))
+        .subcommand(SubCommand::with_name("tune")
+                        .about("Tunes the kernel described by a spec file.")
+                        .arg(Arg::with_name("spec")
+                                 .required(true)
+                                 .help("The TOML or JSON spec file."))
+                        .arg(Arg::with_name("platform")
+                                 .short("p")
+                                 .long("platform")
+                                 .takes_value(true)
+                                 .default_value("any")
+                                 .help("The OpenCL platform: 'any', an index, \
+                                        'name:<substring>' or 'vendor:<substring>'. With \
+                                        'any', the first platform with a matching device \
+                                        is used."))
+                        .arg(Arg::with_name("device")
+                                 .short("d")
+                                 .long("device")
+                                 .takes_value(true)
+                                 .multiple(true)
+                                 .number_of_values(1)
+                                 .default_value("0")
+                                 .help("The OpenCL device: an index, 'gpu', 'cpu', \
+                                        'accelerator', 'name:<substring>' or \
+                                        'vendor:<substring>'. Can be given several times."))
+                        .arg(Arg::with_name("size")
+                                 .short("S")
+                                 .long("size")
+                                 .takes_value(true)
+                                 .multiple(true)
+                                 .number_of_values(1)
+                                 .help("Overrides a problem size of the spec, as NAME=VALUE."))
+                        .arg(Arg::with_name("sweep")
+                                 .long("sweep")
+                                 .takes_value(true)
+                                 .multiple(true)
+                                 .number_of_values(1)
+                                 .help("Tunes for every value of a problem size, as \
+                                        NAME=V1,V2,... Several sweeps form a grid."))
+                        .arg(Arg::with_name("aggregate")
+                                 .long("aggregate")
+                                 .takes_value(true)
+                                 .possible_values(&["mean", "worst"])
+                                 .default_value("mean")
+                                 .help("How the best configuration across all swept sizes is \
+                                        chosen: by its mean or worst slowdown."))
+                        .arg(Arg::with_name("runs")
+                                 .short("r")
+                                 .long("runs")
+                                 .takes_value(true)
+                                 .default_value("10")
+                                 .help("The number of timed runs per configuration."))
+                        .arg(Arg::with_name("strategy")
+                                 .short("s")
+                                 .long("strategy")
+                                 .takes_value(true)
+                                 .possible_values(&["full", "random"])
+                                 .default_value("full")
+                                 .help("The order in which configurations are explored."))
+                        .arg(Arg::with_name("budget")
+                                 .short("b")
+                                 .long("budget")
+                                 .takes_value(true)
+                                 .help("The maximum number of configurations to time."))
+                        .arg(Arg::with_name("peak-gflops")
+                                 .long("peak-gflops")
+                                 .takes_value(true)
+                                 .help("The peak of the device in GFLOPS, to report GFLOPS as a \
+                                        fraction of it. OpenCL does not report it."))
+                        .arg(Arg::with_name("peak-bandwidth")
+                                 .long("peak-bandwidth")
+                                 .takes_value(true)
+                                 .help("The peak memory bandwidth of the device in GB/s, to \
+                                        report bandwidth as a fraction of it."))
+                        .arg(Arg::with_name("output")
+                                 .short("o")
+                                 .long("output")
+                                 .takes_value(true)
+                                 .help("The JSON file to which to save the results."))
+                        .arg(Arg::with_name("log")
+                                 .short("l")
+                                 .long("log")
+                                 .takes_value(true)
+                                 .help("The log file to which to write results as they come."))
+                        .arg(Arg::with_name("log-format")
+                                 .long("log-format")
+                                 .takes_value(true)
+                                 .possible_values(&["csv", "jsonl"])
+                                 .default_value("csv")
+                                 .help("Writes the log as CSV, or as JSON Lines including \
+                                        skipped and failed configurations."))
+                        .arg(Arg::with_name("quiet")
+                                 .short("q")
+                                 .long("quiet")
+                                 .help("Only prints the best configuration.")))
+        .subcommand(SubCommand::with_name("show")
+                        .about("Pretty-prints saved results.")
+                        .arg(Arg::with_name("results")
+                                 .required(true)
+                                 .help("The JSON results file."))
+                        .arg(Arg::with_name("sort")
+                                 .long("sort")
+                                 .takes_value(true)
+                                 .default_value("time")
+                                 .help("Sorts by 'time' or by the value of a parameter."))
+                        .arg(Arg::with_name("top")
+                                 .short("n")
+                                 .long("top")
+                                 .takes_value(true)
+                                 .help("Only shows the first N results.")))
+        .subcommand(SubCommand::with_name("export")
+                        .about("Exports the best configuration of saved results.")
+                        .arg(Arg::with_name("results")
+                                 .required(true)
+                                 .help("The JSON results file."))
+                        .arg(Arg::with_name("format")
+                                 .short("f")
+                                 .long("format")
+                                 .takes_value(true)
+                                 .possible_values(&["defines", "database"])
+                                 .default_value("defines")
+                                 .help("Emits '#define' lines or database entries.")))
+        .subcommand(SubCommand::with_name("select")
+                        .about("Chooses a few configurations covering all problem sizes of \
+                                saved results and prints a dispatch table.")
+                        .arg(Arg::with_name("results")
+                                 .required(true)
+                                 .help("The JSON results file, with one result per size."))
+                        .arg(Arg::with_name("count")
+                                 .short("k")
+                                 .long("count")
+                                 .takes_value(true)
+                                 .default_value("3")
+                                 .help("The maximum number of configurations."))
+                        .arg(Arg::with_name("tolerance")
+                                 .short("t")
+                                 .long("tolerance")
+                                 .takes_value(true)
+                                 .default_value("10")
+                                 .help("How far from its best time, in percent, each size may \
+                                        be.")))
+        .get_matches();
+
+    let result = match matches.subcommand() {
+        ("devices", Some(_)) => devices(),
+        ("tune", Some(m)) => tune(m),
+        ("show", Some(m)) => show(m),
+        ("export", Some(m)) => export(m),
+        ("select", Some(m)) => select(m),
+        _ => Err(matches.usage().into()),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
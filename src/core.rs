@@ -1,34 +1,164 @@
 use std::collections::HashMap;
+use std::collections::hash_map;
+use std::fmt;
+use std::sync::Arc;
 use rand::{thread_rng, Rng};
 use std::time::Duration;
 use std::ops::Index;
 use std::io::Write;
+use std::str::FromStr;
 
-use ocl::{Platform, Context, Device, Queue, Event, Program, Kernel, Buffer, SpatialDims};
-use ocl::flags::CommandQueueProperties;
+use ocl::{Platform, Context, Device, Queue, Event, Program, Kernel, Buffer, Sampler,
+          SpatialDims};
+use ocl::flags::{CommandQueueProperties, MemFlags};
 use ocl::enums::{ProfilingInfo, DeviceInfo, DeviceInfoResult};
 use futures::future::Future;
 
+use args::{self, Arg};
+use data::{self, Access, Init};
+use image::{ImageArg, ImageData, ImageFormat};
+use device::{PlatformSelector, DeviceSelector, DeviceFingerprint, KernelLimits};
+use results::{self, Log, LogFormat, Status, TuningResult, TuningResults};
+use expr;
+
+/// The value of a parameter: an integer, a boolean or one of an enumeration of strings, such
+/// as a data type name.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    Int(i32),
+    Bool(bool),
+    Str(String),
+}
+
+impl Value {
+    /// The value as an integer, with booleans as 0 or 1. Strings have no integer value.
+    pub fn as_int(&self) -> Option<i32> {
+        match *self {
+            Value::Int(x) => Some(x),
+            Value::Bool(x) => Some(x as i32),
+            Value::Str(_) => None,
+        }
+    }
+
+    /// The value as passed to the compiler with `-D`, with booleans as 0 or 1 so that they
+    /// work with `#if`.
+    pub fn define(&self) -> String {
+        match *self {
+            Value::Int(x) => x.to_string(),
+            Value::Bool(x) => (x as i32).to_string(),
+            Value::Str(ref x) => x.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Int(x) => f.pad(&x.to_string()),
+            Value::Bool(x) => f.pad(if x { "true" } else { "false" }),
+            Value::Str(ref x) => f.pad(x),
+        }
+    }
+}
+
+impl From<i32> for Value {
+    fn from(x: i32) -> Self {
+        Value::Int(x)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(x: bool) -> Self {
+        Value::Bool(x)
+    }
+}
+
+impl<'a> From<&'a str> for Value {
+    fn from(x: &'a str) -> Self {
+        Value::Str(x.into())
+    }
+}
+
+/// The value of every parameter in a single configuration.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Config {
+    values: HashMap<String, Value>,
+}
+
+impl<'b> Index<&'b str> for Config {
+    type Output = Value;
+    fn index(&self, index: &'b str) -> &Self::Output {
+        self.get(index).unwrap_or_else(|| panic!("Parameter {} does not exist.", index))
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.values.get(name)
+    }
+
+    /// The integer value of a parameter, see `Value::as_int`. Panics if the parameter does not
+    /// exist or is a string.
+    pub fn int(&self, name: &str) -> i32 {
+        self[name]
+            .as_int()
+            .unwrap_or_else(|| panic!("Parameter {} is not an integer.", name))
+    }
+
+    pub fn insert(&mut self, name: String, value: Value) {
+        self.values.insert(name, value);
+    }
+
+    pub fn iter(&self) -> hash_map::Iter<'_, String, Value> {
+        self.values.iter()
+    }
+}
+
+/// Computes one dimension of a work size from the wrapper's base size in that dimension and
+/// the configuration. An error skips the configuration.
+pub type WorkSizeFn = Arc<dyn Fn(usize, &Config) -> Result<usize, String> + Send + Sync>;
+
+/// An integer function of the configuration which may fail to evaluate, e.g. because of a
+/// division by zero, skipping the configuration.
+pub type Formula = FnWrap<Result<i32, String>>;
+
 #[derive(Clone)]
-pub struct ParameterSet<'a> {
-    pub parameters: Vec<(String, Vec<i32>)>,
-    pub constraints: Vec<FnWrap<'a, bool>>,
-    pub local_memory_needed: Option<FnWrap<'a, i32>>,
+pub struct ParameterSet {
+    pub parameters: Vec<(String, Vec<Value>)>,
+    pub constraints: Vec<FnWrap<bool>>,
+    pub local_memory_needed: Option<Formula>,
     pub mul_local_size: Option<Vec<Option<String>>>,
     pub mul_global_size: Option<Vec<Option<String>>>,
     pub div_global_size: Option<Vec<Option<String>>>,
+    /// Per-dimension global work size, takes precedence over `mul_global_size` and
+    /// `div_global_size`.
+    pub global_size: Option<Vec<WorkSizeFn>>,
+    /// Per-dimension local work size, takes precedence over `mul_local_size`.
+    pub local_size: Option<Vec<WorkSizeFn>>,
+    /// Parameters computed from the tuned ones, in order, so each may use the previous ones.
+    /// They are passed to the compiler and visible to constraints and work sizes, but are not
+    /// searched over.
+    pub derived: Vec<(String, Formula)>,
+    /// Conditions under which a tuned parameter is active, and the value it is fixed to when it
+    /// is not. Configurations which only differ in inactive parameters are generated once.
+    pub conditions: Vec<(String, FnWrap<bool>, Value)>,
 }
 
-impl<'a, 'b> Index<&'b str> for ParameterSet<'a> {
-    type Output = Vec<i32>;
+impl<'b> Index<&'b str> for ParameterSet {
+    type Output = Vec<Value>;
     fn index(&self, index: &'b str) -> &Self::Output {
         self.get(index).unwrap()
     }
 }
 
-impl<'a> ParameterSet<'a> {
-    fn get(&self, key: &str) -> Result<&Vec<i32>, String> {
-        for &(ref k, ref v) in &self.parameters {
+impl ParameterSet {
+    fn get(&self, key: &str) -> Result<&Vec<Value>, String> {
+        for (k, v) in &self.parameters {
             if k == key {
                 return Ok(v);
             }
@@ -39,24 +169,722 @@ impl<'a> ParameterSet<'a> {
     fn len(&self) -> usize {
         self.parameters.len()
     }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.parameters.iter().map(|(k, _)| k.as_str()).collect()
+    }
+
+    pub fn derived_names(&self) -> Vec<&str> {
+        self.derived.iter().map(|(k, _)| k.as_str()).collect()
+    }
+
+    /// The names of the tuned parameters followed by the derived ones.
+    pub fn all_names(&self) -> Vec<&str> {
+        let mut names = self.names();
+        names.extend(self.derived_names());
+        names
+    }
+
+    /// Adds a constraint written as an expression, e.g. `"KWG % KWI == 0"`.
+    pub fn add_constraint(&mut self, src: &str) -> Result<(), String> {
+        let constraint = expr::constraint(src, &self.all_names())?;
+        self.constraints.push(constraint);
+        Ok(())
+    }
+
+    /// Sets the local memory needed by a configuration as an expression.
+    pub fn set_local_memory(&mut self, src: &str) -> Result<(), String> {
+        self.local_memory_needed = Some(expr::formula(src, &self.all_names())?);
+        Ok(())
+    }
+
+    /// Adds a derived parameter written as an expression, e.g. `"MWG / MDIMC"`.
+    pub fn add_derived(&mut self, name: &str, src: &str) -> Result<(), String> {
+        let formula = expr::formula(src, &self.all_names())?;
+        self.derived.push((name.into(), formula));
+        Ok(())
+    }
+
+    /// Makes a tuned parameter active only when the condition holds, e.g. `"SA == 1"`, and
+    /// fixes it to `default` otherwise. The condition may only use the parameters before it.
+    pub fn add_condition(&mut self, name: &str, src: &str, default: Value) -> Result<(), String> {
+        let position = self.names()
+            .iter()
+            .position(|&k| k == name)
+            .ok_or_else(|| format!("Parameter {} does not exist.", name))?;
+        let condition = expr::constraint(src, &self.names()[..position])?;
+        self.conditions.push((name.into(), condition, default));
+        Ok(())
+    }
+
+    /// Whether the indexes are the single representative of their configuration, i.e. every
+    /// inactive parameter has the first index.
+    pub fn is_canonical(&self, indexes: &[usize]) -> bool {
+        self.tuned_config(indexes).1
+    }
+
+    /// The configuration for the given value indexes of the tuned parameters, including the
+    /// derived parameters. Fails if a derived parameter fails to evaluate, e.g. because of a
+    /// division by zero, or is not positive but multiplies or divides a work size.
+    pub fn config(&self, indexes: &[usize]) -> Result<Config, String> {
+        let mut config = self.tuned_config(indexes).0;
+        let lists = [("mul_local_size", &self.mul_local_size),
+                     ("mul_global_size", &self.mul_global_size),
+                     ("div_global_size", &self.div_global_size)];
+        for (key, func) in &self.derived {
+            let value = func.call(&config)
+                .map_err(|e| format!("Derived parameter {} failed: {}", key, e))?;
+            if value <= 0 {
+                let list = lists
+                    .iter()
+                    .find(|(_, list)| {
+                              list.iter()
+                                  .flat_map(|l| l.iter())
+                                  .any(|x| x.as_ref() == Some(key))
+                          });
+                if let Some((list_name, _)) = list {
+                    return Err(format!("Derived parameter {} is {} but is used in {}.",
+                                       key,
+                                       value,
+                                       list_name));
+                }
+            }
+            config.insert(key.clone(), Value::Int(value));
+        }
+        Ok(config)
+    }
+
+    /// Checks that every name used exists and is declared once, that every parameter has
+    /// values and that no work size is divided by zero. With a wrapper, also checks that the
+    /// work size lists match its dimensions. Returns all problems found, one per line.
+    pub fn validate(&self, wrapper: Option<&KernelWrapper>) -> Result<(), String> {
+        let problems = self.problems(wrapper);
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems.join("\n"))
+        }
+    }
+
+    fn problems(&self, wrapper: Option<&KernelWrapper>) -> Vec<String> {
+        fn check_args(args: &[String], names: &[&str], what: &str, problems: &mut Vec<String>) {
+            for arg in args {
+                if !names.contains(&arg.as_str()) {
+                    problems.push(format!("{} uses unknown parameter {}.", what, arg));
+                }
+            }
+        }
+        let mut problems = Vec::new();
+        let names = self.all_names();
+        for (i, name) in names.iter().enumerate() {
+            if names[..i].contains(name) {
+                problems.push(format!("Parameter {} is declared more than once.", name));
+            }
+        }
+        for (name, values) in &self.parameters {
+            if values.is_empty() {
+                problems.push(format!("Parameter {} has no values.", name));
+            }
+        }
+
+        for (i, constraint) in self.constraints.iter().enumerate() {
+            check_args(constraint.args(),
+                       &names,
+                       &format!("Constraint {}", i),
+                       &mut problems);
+        }
+        if let Some(ref local_memory) = self.local_memory_needed {
+            check_args(local_memory.args(), &names, "The local memory", &mut problems);
+        }
+        for (name, formula) in &self.derived {
+            check_args(formula.args(),
+                       &names,
+                       &format!("Derived parameter {}", name),
+                       &mut problems);
+        }
+        for (name, condition, _) in &self.conditions {
+            if self.get(name).is_err() {
+                problems.push(format!("A condition is set on unknown parameter {}.", name));
+            }
+            check_args(condition.args(),
+                       &names,
+                       &format!("The condition of {}", name),
+                       &mut problems);
+        }
+
+        let lists = [("mul_local_size", &self.mul_local_size),
+                     ("mul_global_size", &self.mul_global_size),
+                     ("div_global_size", &self.div_global_size)];
+        for &(list_name, list) in &lists {
+            for name in list.iter().flat_map(|l| l.iter()).filter_map(|x| x.as_ref()) {
+                if !names.contains(&name.as_str()) {
+                    problems.push(format!("{} uses unknown parameter {}.", list_name, name));
+                    continue;
+                }
+                // Derived parameters are integers checked for each configuration
+                let mut values: Vec<&Value> = match self.get(name) {
+                    Ok(values) => values.iter().collect(),
+                    Err(_) => continue,
+                };
+                values.extend(self.conditions
+                                  .iter()
+                                  .filter(|(k, _, _)| k == name)
+                                  .map(|(_, _, default)| default));
+                if values.iter().any(|v| v.as_int().is_none()) {
+                    problems.push(format!("{} uses parameter {} which is not an integer.",
+                                          list_name,
+                                          name));
+                }
+                if let Some(x) = values.iter().filter_map(|v| v.as_int()).find(|&x| x <= 0) {
+                    problems.push(format!("{} uses parameter {} which can be {}.",
+                                          list_name,
+                                          name,
+                                          x));
+                }
+            }
+        }
+
+        if let Some(wrapper) = wrapper {
+            if wrapper.inputs_access.len() != wrapper.inputs_dims.len() {
+                problems.push(format!("The kernel has {} buffers but inputs_access has {} \
+                                       entries.",
+                                      wrapper.inputs_dims.len(),
+                                      wrapper.inputs_access.len()));
+            }
+            for (i, dims) in wrapper.inputs_dims.iter().enumerate() {
+                if dims.is_empty() {
+                    problems.push(format!("Buffer {} has no dimensions.", i));
+                }
+            }
+            let global = Tuner::dims(&wrapper.global_base).len();
+            let local = Tuner::dims(&wrapper.local_base).len();
+            if global != local {
+                problems.push(format!("The kernel has a {}-d global base size but a {}-d local \
+                                       base size.",
+                                      global,
+                                      local));
+            }
+            let lengths = [("mul_local_size", self.mul_local_size.as_ref().map(|l| l.len()), local),
+                           ("mul_global_size",
+                            self.mul_global_size.as_ref().map(|l| l.len()),
+                            global),
+                           ("div_global_size",
+                            self.div_global_size.as_ref().map(|l| l.len()),
+                            global),
+                           ("global_size", self.global_size.as_ref().map(|l| l.len()), global),
+                           ("local_size", self.local_size.as_ref().map(|l| l.len()), local)];
+            if let Some(ref args) = wrapper.args {
+                let has_dim = |b: usize, d: usize| {
+                    wrapper.inputs_dims.get(b).and_then(|dims| dims.get(d)).is_some()
+                };
+                for (i, arg) in args.iter().enumerate() {
+                    match *arg {
+                        Arg::Buffer(b) if b >= wrapper.inputs_dims.len() => {
+                            problems.push(format!("Argument {} uses buffer {} which does not \
+                                                   exist.",
+                                                  i,
+                                                  b))
+                        }
+                        Arg::Dim(b, d) if !has_dim(b, d) => {
+                            problems.push(format!("Argument {} uses dimension {} of buffer {} \
+                                                   which does not exist.",
+                                                  i,
+                                                  d,
+                                                  b))
+                        }
+                        Arg::Image(j) if j >= wrapper.images.len() => {
+                            problems.push(format!("Argument {} uses image {} which does not \
+                                                   exist.",
+                                                  i,
+                                                  j))
+                        }
+                        Arg::Local(ref bytes) => {
+                            check_args(bytes.args(),
+                                       &names,
+                                       &format!("Argument {}", i),
+                                       &mut problems)
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            for (i, image) in wrapper.images.iter().enumerate() {
+                let mut formats = Vec::new();
+                match image.format_param {
+                    Some(ref name) => {
+                        match self.get(name) {
+                            Ok(values) => {
+                                for value in values {
+                                    match ImageFormat::from_str(&value.define()) {
+                                        Ok(format) => formats.push(format),
+                                        Err(_) => {
+                                            problems.push(format!("The format of image {} uses \
+                                                                   parameter {} whose value \
+                                                                   '{}' is not an image format.",
+                                                                  i,
+                                                                  name,
+                                                                  value))
+                                        }
+                                    }
+                                }
+                            }
+                            Err(_) => {
+                                problems.push(format!("The format of image {} uses unknown \
+                                                       parameter {}.",
+                                                      i,
+                                                      name))
+                            }
+                        }
+                    }
+                    None => formats.push(image.format),
+                }
+                if image.init.is_none() && image.access != Access::Output &&
+                   formats.iter().any(ImageFormat::is_integer) {
+                    problems.push(format!("Image {} has an integer format and needs an explicit \
+                                           init.",
+                                          i))
+                }
+            }
+            for (i, padding) in wrapper.inputs_padding.iter().enumerate() {
+                match wrapper.inputs_dims.get(i) {
+                    Some(dims) if !padding.is_empty() && padding.len() != dims.len() => {
+                        problems.push(format!("The padding of buffer {} has {} dimensions but \
+                                               the buffer has {}.",
+                                              i,
+                                              padding.len(),
+                                              dims.len()))
+                    }
+                    None => problems.push(format!("Buffer {} is padded but does not exist.", i)),
+                    _ => {}
+                }
+            }
+            for &(list_name, len, dims) in &lengths {
+                match len {
+                    Some(len) if len != dims => {
+                        problems.push(format!("{} has {} dimensions but the kernel has {}.",
+                                              list_name,
+                                              len,
+                                              dims))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        problems
+    }
+
+    fn tuned_config(&self, indexes: &[usize]) -> (Config, bool) {
+        let mut config = Config::new();
+        let mut canonical = true;
+        for ((key, values), &i) in self.parameters.iter().zip(indexes.iter()) {
+            let value = match self.conditions.iter().find(|(k, _, _)| k == key) {
+                Some((_, condition, default)) if !condition.call(&config) => {
+                    canonical = canonical && i == 0;
+                    default.clone()
+                }
+                _ => values[i].clone(),
+            };
+            config.insert(key.clone(), value);
+        }
+        (config, canonical)
+    }
 }
 
-#[derive(Clone, Debug)]
+/// Builds a `ParameterSet` from parameters and expressions over their names, checking when
+/// building that every name used exists.
+///
+/// Parameters keep the order in which they are first declared, and declaring one again
+/// replaces its values, so kernel specific builders can declare all parameters up front and
+/// let their setters fill in the values.
+#[derive(Clone, Debug, Default)]
+pub struct ParameterSetBuilder {
+    parameters: Vec<(String, Vec<Value>)>,
+    derived: Vec<(String, String)>,
+    conditions: Vec<(String, String, Option<Value>)>,
+    constraints: Vec<String>,
+    constraint_fns: Vec<FnWrap<bool>>,
+    local_memory: Option<String>,
+    mul_local_size: Option<Vec<Option<String>>>,
+    mul_global_size: Option<Vec<Option<String>>>,
+    div_global_size: Option<Vec<Option<String>>>,
+    global_size: Option<Vec<String>>,
+    local_size: Option<Vec<String>>,
+}
+
+impl ParameterSetBuilder {
+    pub fn new() -> Self {
+        ParameterSetBuilder::default()
+    }
+
+    pub fn param<V: Into<Value>>(mut self, name: &str, values: Vec<V>) -> Self {
+        let values: Vec<Value> = values.into_iter().map(Into::into).collect();
+        match self.parameters.iter().position(|(k, _)| k == name) {
+            Some(i) => self.parameters[i].1 = values,
+            None => self.parameters.push((name.into(), values)),
+        }
+        self
+    }
+
+    /// See `ParameterSet::add_derived`.
+    pub fn derived(mut self, name: &str, src: &str) -> Self {
+        self.derived.push((name.into(), src.into()));
+        self
+    }
+
+    /// See `ParameterSet::add_condition`, with the first value of the parameter as default.
+    pub fn condition(mut self, name: &str, src: &str) -> Self {
+        self.conditions.push((name.into(), src.into(), None));
+        self
+    }
+
+    /// See `ParameterSet::add_condition`.
+    pub fn condition_default<V: Into<Value>>(mut self, name: &str, src: &str, default: V) -> Self {
+        self.conditions.push((name.into(), src.into(), Some(default.into())));
+        self
+    }
+
+    pub fn constraint(mut self, src: &str) -> Self {
+        self.constraints.push(src.into());
+        self
+    }
+
+    pub fn constraint_fn(mut self, constraint: FnWrap<bool>) -> Self {
+        self.constraint_fns.push(constraint);
+        self
+    }
+
+    pub fn local_memory(mut self, src: &str) -> Self {
+        self.local_memory = Some(src.into());
+        self
+    }
+
+    /// Multiplies each dimension of the local base size by a parameter, `None` for none.
+    pub fn mul_local_size(mut self, names: Vec<Option<&str>>) -> Self {
+        self.mul_local_size = Some(names.into_iter().map(|x| x.map(Into::into)).collect());
+        self
+    }
+
+    pub fn mul_global_size(mut self, names: Vec<Option<&str>>) -> Self {
+        self.mul_global_size = Some(names.into_iter().map(|x| x.map(Into::into)).collect());
+        self
+    }
+
+    pub fn div_global_size(mut self, names: Vec<Option<&str>>) -> Self {
+        self.div_global_size = Some(names.into_iter().map(|x| x.map(Into::into)).collect());
+        self
+    }
+
+    /// One expression per dimension for the global work size, see `ParameterSet::global_size`.
+    pub fn global_size(mut self, srcs: Vec<&str>) -> Self {
+        self.global_size = Some(srcs.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn local_size(mut self, srcs: Vec<&str>) -> Self {
+        self.local_size = Some(srcs.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Builds the parameter set, returning every problem found, one per line, see
+    /// `ParameterSet::validate`.
+    pub fn build(self) -> Result<ParameterSet, String> {
+        let mut params = ParameterSet {
+            parameters: self.parameters,
+            constraints: self.constraint_fns,
+            local_memory_needed: None,
+            mul_local_size: self.mul_local_size,
+            mul_global_size: self.mul_global_size,
+            div_global_size: self.div_global_size,
+            global_size: None,
+            local_size: None,
+            derived: Vec::new(),
+            conditions: Vec::new(),
+        };
+        let mut problems = Vec::new();
+        for (name, src) in self.derived {
+            if let Err(e) = params.add_derived(&name, &src) {
+                problems.push(e);
+            }
+        }
+        for (name, src, default) in self.conditions {
+            let default = default.or_else(|| {
+                                              params.get(&name)
+                                                  .ok()
+                                                  .and_then(|v| v.first().cloned())
+                                          });
+            // Unknown parameters and empty value lists are reported by `validate`
+            if let Some(default) = default {
+                if let Err(e) = params.add_condition(&name, &src, default) {
+                    problems.push(e);
+                }
+            }
+        }
+        for src in self.constraints {
+            if let Err(e) = params.add_constraint(&src) {
+                problems.push(e);
+            }
+        }
+        if let Some(src) = self.local_memory {
+            if let Err(e) = params.set_local_memory(&src) {
+                problems.push(e);
+            }
+        }
+        params.global_size = ParameterSetBuilder::work_size(&self.global_size,
+                                                            &params,
+                                                            &mut problems);
+        params.local_size = ParameterSetBuilder::work_size(&self.local_size,
+                                                           &params,
+                                                           &mut problems);
+        problems.extend(params.problems(None));
+        if problems.is_empty() {
+            Ok(params)
+        } else {
+            Err(problems.join("\n"))
+        }
+    }
+
+    fn work_size(srcs: &Option<Vec<String>>, params: &ParameterSet, problems: &mut Vec<String>)
+                 -> Option<Vec<WorkSizeFn>> {
+        srcs.as_ref().map(|srcs| {
+            srcs.iter()
+                .filter_map(|src| match expr::Expr::parse_with_names(src, &params.all_names()) {
+                                Ok(e) => Some(e.into_work_size()),
+                                Err(e) => {
+                                    problems.push(e);
+                                    None
+                                }
+                            })
+                .collect()
+        })
+    }
+}
+
+#[derive(Clone)]
 pub struct KernelWrapper {
     pub scalar_inputs: Vec<i32>,
-    pub inputs_dims: Vec<(usize, usize)>,
+    /// The shape of each buffer, of any rank but at least one, in row-major order.
+    pub inputs_dims: Vec<Vec<usize>>,
+    /// Optionally, one function per dimension of a buffer computing its padded size from the
+    /// unpadded size and the configuration, e.g. rounding `M` up to a multiple of `MWG`. An
+    /// empty list means no padding.
+    pub inputs_padding: Vec<Vec<WorkSizeFn>>,
+    /// How each input buffer is filled, in the order of `inputs_dims`. Buffers without one
+    /// use `Init::default()`.
+    pub inputs_init: Vec<Init>,
+    /// Whether each buffer is an input, output or both, in the order of `inputs_dims`. Every
+    /// buffer needs one, since inputs are read-only for the kernel.
+    pub inputs_access: Vec<Access>,
     pub src: String,
     pub name: String,
+    pub problem_size: Vec<(String, usize)>,
     pub ref_name: Option<String>,
     pub global_base: SpatialDims,
     pub local_base: SpatialDims,
+    /// The kernel arguments in order. Takes precedence over binding all `scalar_inputs` as
+    /// `int`s followed by all buffers.
+    pub args: Option<Vec<Arg>>,
+    /// Images, which are only passed to the kernel through `Arg::Image` in `args`.
+    pub images: Vec<ImageArg>,
+    /// The floating point operations of one launch for the problem size, e.g. `2 * M * N * K`
+    /// for gemm, to report GFLOPS.
+    pub flops: Option<u64>,
+    /// The bytes read and written by one launch for the problem size, to report the effective
+    /// bandwidth.
+    pub bytes: Option<u64>,
+}
+
+impl fmt::Debug for KernelWrapper {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The padding functions can't be printed, only how many each buffer has.
+        let padding: Vec<usize> = self.inputs_padding.iter().map(|p| p.len()).collect();
+        f.debug_struct("KernelWrapper")
+            .field("scalar_inputs", &self.scalar_inputs)
+            .field("inputs_dims", &self.inputs_dims)
+            .field("inputs_padding", &padding)
+            .field("inputs_init", &self.inputs_init)
+            .field("inputs_access", &self.inputs_access)
+            .field("src", &self.src)
+            .field("name", &self.name)
+            .field("problem_size", &self.problem_size)
+            .field("ref_name", &self.ref_name)
+            .field("global_base", &self.global_base)
+            .field("local_base", &self.local_base)
+            .field("args", &self.args)
+            .field("images", &self.images)
+            .field("flops", &self.flops)
+            .field("bytes", &self.bytes)
+            .finish()
+    }
+}
+
+impl KernelWrapper {
+    /// The access of the buffer at `index`, see `inputs_access`.
+    pub fn access(&self, index: usize) -> Access {
+        self.inputs_access[index]
+    }
+
+    /// The padded shape of the buffer at `index` for a configuration, see `inputs_padding`.
+    pub fn buffer_dims(&self, index: usize, config: &Config) -> Result<Vec<usize>, String> {
+        let dims = &self.inputs_dims[index];
+        match self.inputs_padding.get(index) {
+            Some(padding) if !padding.is_empty() => {
+                dims.iter()
+                    .zip(padding.iter())
+                    .map(|(&d, pad)| pad(d, config).map(|p| p.max(d)))
+                    .collect()
+            }
+            _ => Ok(dims.clone()),
+        }
+    }
+
+    /// The total number of bytes of the `__local` arguments for a configuration.
+    pub fn local_args_size(&self, config: &Config) -> Result<u64, String> {
+        let mut size = 0;
+        for arg in self.args.iter().flat_map(|args| args.iter()) {
+            size += arg.local_bytes(config)?;
+        }
+        Ok(size)
+    }
+}
+
+/// The buffers, images and samplers of a kernel, with the padded shapes of the buffers, the
+/// formats of the images and the initial contents of the in/out buffers and images.
+struct Buffers {
+    buffers: Vec<Buffer<f32>>,
+    dims: Vec<Vec<usize>>,
+    resets: Vec<(usize, Vec<f32>)>,
+    images: Vec<ImageData>,
+    image_resets: Vec<(usize, Vec<f32>)>,
+    formats: Vec<ImageFormat>,
+    samplers: Vec<Sampler>,
+}
+
+/// The order in which the configurations are explored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Strategy {
+    /// All configurations in order, the last parameter changing fastest.
+    Full,
+    /// All configurations in a random order, usually combined with a budget.
+    Random,
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(Strategy::Full),
+            "random" => Ok(Strategy::Random),
+            _ => Err(format!("Unknown strategy '{}', expected 'full' or 'random'.", s)),
+        }
+    }
+}
+
+/// The value indexes of the canonical configurations in the order of a strategy, generated one
+/// at a time. The `n`th configuration of a random order is the one at position `permute(n)` in
+/// the full order, where `permute` is a Feistel network with random round keys over the
+/// smallest power of four not below the total. Positions past the total are fed back through
+/// the network until they land in range, which keeps it a bijection of the positions without
+/// storing them.
+struct Configurations<'a> {
+    params: &'a ParameterSet,
+    total: u64,
+    next: u64,
+    /// The round keys, none for the full order.
+    keys: Vec<u64>,
+    /// The bits of each half of a permuted position.
+    half_bits: u32,
+}
+
+impl<'a> Configurations<'a> {
+    fn new(params: &'a ParameterSet, strategy: Strategy) -> Result<Self, String> {
+        let keys = match strategy {
+            Strategy::Full => Vec::new(),
+            Strategy::Random => {
+                let mut rng = thread_rng();
+                (0..6).map(|_| rng.gen()).collect()
+            }
+        };
+        Configurations::with_keys(params, keys)
+    }
+
+    fn with_keys(params: &'a ParameterSet, keys: Vec<u64>) -> Result<Self, String> {
+        let total = params
+            .parameters
+            .iter()
+            .try_fold(1u64, |total, (_, values)| total.checked_mul(values.len() as u64))
+            .ok_or_else(|| "The number of configurations does not fit in 64 bits.".to_string())?;
+        let bits = 64 - total.saturating_sub(1).leading_zeros();
+        Ok(Configurations {
+               params,
+               total,
+               next: 0,
+               keys,
+               half_bits: bits.div_ceil(2).max(1),
+           })
+    }
+
+    fn permute(&self, n: u64) -> u64 {
+        if self.keys.is_empty() {
+            return n;
+        }
+        let mask = (1 << self.half_bits) - 1;
+        let mut position = n;
+        loop {
+            let (mut left, mut right) = (position >> self.half_bits, position & mask);
+            for &key in &self.keys {
+                let mixed = left ^ (mix(right ^ key) & mask);
+                left = right;
+                right = mixed;
+            }
+            position = (left << self.half_bits) | right;
+            if position < self.total {
+                return position;
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Configurations<'a> {
+    type Item = Vec<usize>;
+
+    fn next(&mut self) -> Option<Vec<usize>> {
+        while self.next < self.total {
+            let mut position = self.permute(self.next);
+            self.next += 1;
+            // The last parameter changes fastest
+            let mut indexes = vec![0; self.params.len()];
+            for (i, (_, values)) in self.params.parameters.iter().enumerate().rev() {
+                indexes[i] = (position % values.len() as u64) as usize;
+                position /= values.len() as u64;
+            }
+            if self.params.is_canonical(&indexes) {
+                return Some(indexes);
+            }
+        }
+        None
+    }
+}
+
+/// The round function of the Feistel network, the finaliser of SplitMix64.
+fn mix(x: u64) -> u64 {
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
 }
 
 #[derive(Clone, Debug)]
 pub struct Tuner {
+    platform: Platform,
     device: Device,
     context: Context,
     queue: Queue,
+    verbose: bool,
+    strategy: Strategy,
+    budget: Option<usize>,
+    peak_gflops: Option<f64>,
+    peak_bandwidth: Option<f64>,
+    log_format: LogFormat,
 }
 
 impl Default for Tuner {
@@ -67,8 +895,17 @@ impl Default for Tuner {
 
 impl Tuner {
     pub fn new(platform_id: usize, device_id: usize) -> Self {
-        let platform = Platform::list()[platform_id];
-        let device = Device::list_all(&platform).unwrap()[device_id];
+        Tuner::select(&PlatformSelector::Index(platform_id),
+                      &DeviceSelector::Index(device_id))
+                .unwrap()
+    }
+
+    pub fn select(platform: &PlatformSelector, device: &DeviceSelector) -> Result<Self, String> {
+        let (platform, device) = platform.select_device(device)?;
+        Ok(Tuner::from_device(platform, device))
+    }
+
+    pub fn from_device(platform: Platform, device: Device) -> Self {
         println!("Platform: {} - {}", platform.name(), platform.version());
         println!("Device: {} by {}", device.name(), device.vendor());
         let context = Context::builder()
@@ -79,301 +916,644 @@ impl Tuner {
         let queue_flags = Some(CommandQueueProperties::new().profiling());
         let queue = Queue::new(&context, device, queue_flags).unwrap();
         Tuner {
-            device: device,
-            context: context,
-            queue: queue,
+            platform,
+            device,
+            context,
+            queue,
+            verbose: true,
+            strategy: Strategy::Full,
+            budget: None,
+            peak_gflops: None,
+            peak_bandwidth: None,
+            log_format: LogFormat::default(),
         }
     }
 
+    /// Whether to print progress to the standard output and skipped configurations to the
+    /// standard error.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    pub fn strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// The maximum number of configurations to time, invalid configurations do not count.
+    pub fn budget(mut self, budget: Option<usize>) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// The peak of the device in GFLOPS, which OpenCL does not report. Without it the results
+    /// have no percentage of the peak.
+    pub fn peak_gflops(mut self, peak: Option<f64>) -> Self {
+        self.peak_gflops = peak;
+        self
+    }
+
+    /// The peak memory bandwidth of the device in GB/s, which OpenCL does not report.
+    pub fn peak_bandwidth(mut self, peak: Option<f64>) -> Self {
+        self.peak_bandwidth = peak;
+        self
+    }
+
+    /// The format of the log file given to `tune`, CSV by default.
+    pub fn log_format(mut self, format: LogFormat) -> Self {
+        self.log_format = format;
+        self
+    }
+
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    pub fn fingerprint(&self) -> DeviceFingerprint {
+        DeviceFingerprint::new(&self.platform, &self.device)
+    }
+
+    /// Tunes the kernel over every configuration of the parameter set, in the order given by
+    /// the strategy. Fails if the parameter set is invalid or the log file can't be written;
+    /// configurations which fail to evaluate, build or run are skipped.
     pub fn tune(&self, wrapper: KernelWrapper, params: ParameterSet, runs: usize,
-                log_file: Option<&str>) {
-        let mut log_file = log_file.map(|x| ::std::fs::File::create(x).unwrap());
-        // Generate buffers
-        let buffers: Vec<Buffer<f32>> = wrapper
-            .inputs_dims
+                log_file: Option<&str>)
+                -> Result<TuningResults, String> {
+        params
+            .validate(Some(&wrapper))
+            .map_err(|e| format!("Invalid parameter set:\n{}", e))?;
+        if runs == 0 {
+            return Err("The number of runs must be at least 1.".into());
+        }
+        // Seeds are drawn once, so buffers reallocated for a different padding get the same data
+        let inits = Tuner::inits(&wrapper);
+        let mut buffers: Option<Buffers> = None;
+
+        let mut results = TuningResults::new(self.fingerprint(),
+                                             wrapper.name.clone(),
+                                             wrapper.problem_size.clone());
+        results.inputs = inits
             .iter()
-            .map(|&(ref r, ref c)| {
-                     Buffer::<f32>::builder()
-                         .queue(self.queue.clone())
-                         .dims(SpatialDims::Two(*r, *c))
-                         .build()
-                         .unwrap()
+            .enumerate()
+            .map(|(i, init)| if i >= wrapper.inputs_dims.len() {
+                     match wrapper.images[i - wrapper.inputs_dims.len()].access {
+                         Access::Output => "image(output)".into(),
+                         _ => format!("image({})", init),
+                     }
+                 } else if wrapper.access(i) == Access::Output {
+                     "output".into()
+                 } else {
+                     init.to_string()
                  })
             .collect();
-        // Populate buffers
-        let mut rng = thread_rng();
-        for buf in &buffers {
-            let vec = rng.gen_iter::<f32>()
-                .take(buf.len())
-                .collect::<Vec<f32>>();
-            buf.write(&vec).enq().unwrap();
-        }
-
-        if let Some(f) = log_file.as_mut() {
-            Tuner::write_header(&params.parameters, f).expect("Writing header failed.");
+        results.flops = wrapper.flops;
+        results.bytes = wrapper.bytes;
+        results.peak_gflops = self.peak_gflops;
+        results.peak_bandwidth = self.peak_bandwidth;
+        let mut log = match log_file {
+            Some(f) => Some(Log::create(f, self.log_format, &results, &params.all_names())?),
+            None => None,
+        };
+        if self.verbose {
+            results::print_header(&params.all_names(), &results.metric_names());
         }
-        Tuner::print_header(&params.parameters);
 
-        let mut indexes = vec![0; params.len()];
         let local_memory_limit = match self.device.info(DeviceInfo::LocalMemSize) {
             DeviceInfoResult::LocalMemSize(s) => s,
             _ => unreachable!(),
         };
-        loop {
+        let work_group_limit = match self.device.info(DeviceInfo::MaxWorkGroupSize) {
+            DeviceInfoResult::MaxWorkGroupSize(s) => s,
+            _ => unreachable!(),
+        };
+        let work_item_limits = match self.device.info(DeviceInfo::MaxWorkItemSizes) {
+            DeviceInfoResult::MaxWorkItemSizes(s) => s,
+            _ => unreachable!(),
+        };
+        for indexes in Configurations::new(&params, self.strategy)? {
+            if let Some(budget) = self.budget {
+                if results.results.len() >= budget {
+                    break;
+                }
+            }
             // Fill in parameters
-            //            let mut context = tera::Context::new();
-            let config: HashMap<String, i32> = params
-                .parameters
+            let mut skipped = None;
+            let config = match params.config(&indexes) {
+                Ok(config) => config,
+                Err(e) => {
+                    skipped = Some(e);
+                    params.tuned_config(&indexes).0
+                }
+            };
+            // Configuration parameters in order, empty for derived ones which failed
+            let ordered = params
+                .all_names()
                 .iter()
-                .zip(indexes.iter())
-                .map(|(&(ref key, ref values), &i)| {
-                         let v = values[i];
-                         //                    context.add(&key, &v);
-                         //                    print!("|{:>10}", v);
-                         (key.clone(), v)
-                     })
-                .collect();
+                .map(|&k| config.get(k).cloned().unwrap_or_else(|| Value::Str(String::new())))
+                .collect::<Vec<_>>();
 
             // Verify constraints
-            let mut failed = false;
-            for (i, constraint) in params.constraints.iter().enumerate() {
-                let args: Vec<_> = constraint.args.iter().map(|&x| config[x]).collect();
-                if !(constraint.func)(&args) {
-                    writeln!(&mut ::std::io::stderr(), "Constrain {} has failed.", i).unwrap();
-                    failed = true;
-                    break;
+            if skipped.is_none() {
+                for (i, constraint) in params.constraints.iter().enumerate() {
+                    if !constraint.call(&config) {
+                        skipped = Some(format!("Constraint {} has failed.", i));
+                        break;
+                    }
                 }
             }
-            if let Some(ref local_memory) = params.local_memory_needed {
-                let args: Vec<_> = local_memory.args.iter().map(|&x| config[x]).collect();
-                let needed = (local_memory.func)(&args) as u64;
-                if local_memory_limit < needed {
-                    writeln!(&mut ::std::io::stderr(),
-                             "Setting exceeds maximum local memory - {}/{}.",
-                             needed,
-                             local_memory_limit)
-                            .unwrap();
-                    failed = true;
+            if skipped.is_none() {
+                match Tuner::local_memory_needed(&wrapper, &params, &config) {
+                    Ok(needed) if local_memory_limit < needed => {
+                        skipped = Some(format!("Setting exceeds maximum local memory - {}/{}.",
+                                               needed,
+                                               local_memory_limit));
+                    }
+                    Ok(_) => {}
+                    Err(e) => skipped = Some(e),
                 }
             }
-            if !failed {
-                // Run the kernel
-                let time = self.run_single_kernel(runs, &wrapper, &params, &config, &buffers);
-                // Configuration parameters in order
-                let ordered = params
-                    .parameters
-                    .iter()
-                    .map(|&(ref k, _)| config[k])
-                    .collect::<Vec<_>>();
-                // Print time
-                if let Some(f) = log_file.as_mut() {
-                    Tuner::write_parameters(&ordered[..], time, f).expect("Writing parameters \
-                                                                           failed.");
+            let mut work_sizes = None;
+            if skipped.is_none() {
+                match Tuner::calculate_work_sizes(&wrapper, &params, &config) {
+                    Ok((gws, lws)) => {
+                        if let Err(reason) = Tuner::check_work_group_size(&lws,
+                                                                          work_group_limit,
+                                                                          &work_item_limits) {
+                            skipped = Some(reason);
+                        }
+                        work_sizes = Some((Tuner::dims(&gws), Tuner::dims(&lws)));
+                    }
+                    Err(e) => skipped = Some(e),
                 }
-                Tuner::print_parameters(&ordered[..], time);
             }
-            // Facilitate iteration over all possible combinations
-            let mut last: i32 = indexes.len() as i32 - 1;
-            while last >= 0 &&
-                  indexes[last as usize] == params.parameters[last as usize].1.len() - 1 {
-                last -= 1;
+            let mut dims = Vec::new();
+            if skipped.is_none() {
+                match (0..wrapper.inputs_dims.len())
+                          .map(|i| wrapper.buffer_dims(i, &config))
+                          .collect() {
+                    Ok(padded) => dims = padded,
+                    Err(e) => skipped = Some(e),
+                }
             }
-            if last == -1 {
-                break;
+            // Run the kernel
+            let status = if let Some(reason) = skipped {
+                Status::Skipped(reason)
+            } else {
+                let formats = wrapper
+                    .images
+                    .iter()
+                    .map(|image| image.format(&config))
+                    .collect::<Result<Vec<_>, _>>();
+                let current = buffers.as_ref().map(|b| (&b.dims, &b.formats));
+                let created = match formats {
+                    Ok(ref formats) if current == Some((&dims, formats)) => Ok(()),
+                    Ok(_) => {
+                        self.create_buffers(&wrapper, &inits, &config)
+                            .map(|b| buffers = Some(b))
+                    }
+                    Err(e) => Err(e),
+                };
+                match created.and_then(|_| {
+                    self.run_single_kernel(runs,
+                                           &wrapper,
+                                           &params,
+                                           &config,
+                                           buffers.as_ref().unwrap())
+                }) {
+                    Ok(status) => status,
+                    Err(reason) => Status::Failed(reason),
+                }
+            };
+            if let Some(log) = log.as_mut() {
+                log.write(&results, &ordered, &status, work_sizes)
+                    .map_err(|e| format!("Writing the log failed: {}", e))?;
             }
-            indexes[last as usize] += 1;
-            for i in indexes.iter_mut().skip(last as usize + 1) {
-                *i = 0;
+            match status {
+                Status::Skipped(ref reason) |
+                Status::Failed(ref reason) => {
+                    if self.verbose {
+                        writeln!(&mut ::std::io::stderr(), "{}", reason).unwrap();
+                    }
+                }
+                Status::Ok(_, time, kernel_limits) => {
+                    if self.verbose {
+                        results::print_row(&ordered[..], time, &results.metrics(time));
+                    }
+                    results
+                        .results
+                        .push(TuningResult {
+                                  parameters: params
+                                      .names()
+                                      .iter()
+                                      .map(|&k| (k.into(), config[k].clone()))
+                                      .collect(),
+                                  derived: params
+                                      .derived_names()
+                                      .iter()
+                                      .map(|&k| (k.into(), config[k].clone()))
+                                      .collect(),
+                                  time,
+                                  kernel_limits,
+                              });
+                }
             }
         }
+        Ok(results)
     }
 
-    fn run_single_kernel(&self, runs: usize, wrapper: &KernelWrapper, params: &ParameterSet,
-                         config: &HashMap<String, i32>, buffers: &[Buffer<f32>])
-                         -> Duration {
-        let (gws, lws) = Tuner::calculate_work_sizes(wrapper, params, config);
-
-        // Build the program with all defines
-        let mut program = Program::builder();
-        for (&ref k, &ref v) in config.iter() {
-            program = program.cmplr_def(k.clone(), *v);
+    /// Runs the kernel once with the given configuration and reads back the output and in/out
+    /// buffers, in order and including any padding, e.g. to verify them against a reference.
+    pub fn run(&self, wrapper: &KernelWrapper, params: &ParameterSet, config: &Config)
+               -> Result<Vec<Vec<f32>>, String> {
+        params.validate(Some(wrapper))?;
+        let buffers = self.create_buffers(wrapper, &Tuner::inits(wrapper), config)?;
+        let (gws, lws) = Tuner::calculate_work_sizes(wrapper, params, config)?;
+        let (kernel, kernel_limits) = self.build_kernel(wrapper, config, &buffers, gws, lws)?;
+        Tuner::check_kernel_limits(&lws, &kernel_limits)?;
+        kernel.cmd().enq().map_err(|e| e.to_string())?;
+        let mut outputs = Vec::new();
+        for (i, buffer) in buffers.buffers.iter().enumerate() {
+            if wrapper.access(i) != Access::Input {
+                let mut data = vec![0.0; buffer.len()];
+                buffer.read(&mut data).enq().map_err(|e| e.to_string())?;
+                outputs.push(data);
+            }
         }
-        let program = program
-            .devices(self.device)
-            .src(wrapper.src.clone())
-            .build(&self.context)
-            .unwrap();
+        Ok(outputs)
+    }
 
-        // Make kernel
-        let mut kernel = Kernel::new(wrapper.name.clone(), &program)
-            .unwrap()
-            .queue(self.queue.clone())
-            .gws(gws)
-            .lws(lws);
+    /// The initialiser of every buffer followed by those of the images, with seeds drawn for
+    /// random ones.
+    fn inits(wrapper: &KernelWrapper) -> Vec<Init> {
+        (0..wrapper.inputs_dims.len())
+            .map(|i| wrapper.inputs_init.get(i).cloned().unwrap_or_default())
+            .chain(wrapper.images.iter().map(|image| image.init.clone().unwrap_or_default()))
+            .map(|init| init.seeded())
+            .collect()
+    }
 
-        println!("GWS: {:?}, LWS: {:?}", gws, lws);
-        // Add arguments
-        for &i in &wrapper.scalar_inputs {
-            kernel = kernel.arg_scl(i);
+    /// Allocates the buffers of the wrapper, padded for the configuration, and initialises all
+    /// but the output ones. Padding is filled with zeros.
+    fn create_buffers(&self, wrapper: &KernelWrapper, inits: &[Init], config: &Config)
+                      -> Result<Buffers, String> {
+        let mut buffers = Buffers {
+            buffers: Vec::new(),
+            dims: Vec::new(),
+            resets: Vec::new(),
+            images: Vec::new(),
+            image_resets: Vec::new(),
+            formats: Vec::new(),
+            samplers: Vec::new(),
+        };
+        for (i, dims) in wrapper.inputs_dims.iter().enumerate() {
+            let padded = wrapper.buffer_dims(i, config)?;
+            let access = wrapper.access(i);
+            let flags = match access {
+                Access::Input => MemFlags::new().read_only(),
+                Access::Output => MemFlags::new().write_only(),
+                Access::InOut => MemFlags::new().read_write(),
+            };
+            let buffer = Buffer::<f32>::builder()
+                .queue(self.queue.clone())
+                .flags(flags)
+                .dims(SpatialDims::One(padded.iter().product()))
+                .build()
+                .map_err(|e| e.to_string())?;
+            if access != Access::Output {
+                let data = data::pad(&inits[i].generate(dims.iter().product())?, dims, &padded);
+                buffer.write(&data).enq().map_err(|e| e.to_string())?;
+                if access == Access::InOut {
+                    buffers.resets.push((i, data));
+                }
+            }
+            buffers.buffers.push(buffer);
+            buffers.dims.push(padded);
+        }
+        for (i, image) in wrapper.images.iter().enumerate() {
+            let format = image.format(config)?;
+            let data = image.generate(format, &inits[wrapper.inputs_dims.len() + i])?;
+            buffers.images.push(image.create(&self.queue, format, &data)?);
+            if image.access == Access::InOut {
+                buffers.image_resets.push((i, data));
+            }
+            buffers.formats.push(format);
         }
-        for &ref buffer in buffers {
-            kernel = kernel.arg_buf(buffer);
+        for arg in wrapper.args.iter().flat_map(|args| args.iter()) {
+            if let Arg::Sampler(ref sampler) = *arg {
+                buffers.samplers.push(sampler.create(&self.context)?);
+            }
+        }
+        Ok(buffers)
+    }
+
+    /// Times a configuration, or skips it when the compiled kernel cannot run its work groups.
+    /// Errors are failures to build or run the kernel.
+    fn run_single_kernel(&self, runs: usize, wrapper: &KernelWrapper, params: &ParameterSet,
+                         config: &Config, buffers: &Buffers)
+                         -> Result<Status, String> {
+        let (gws, lws) = Tuner::calculate_work_sizes(wrapper, params, config)?;
+        let (kernel, kernel_limits) = self.build_kernel(wrapper, config, buffers, gws, lws)?;
+        if let Err(reason) = Tuner::check_kernel_limits(&lws, &kernel_limits) {
+            return Ok(Status::Skipped(reason));
         }
 
         // Run the kernel
         let mut times = Vec::new();
         for _ in 0..runs {
+            // Reset in/out buffers and images, outside of the timed event
+            for &(i, ref data) in &buffers.resets {
+                buffers.buffers[i].write(data).enq().map_err(|e| e.to_string())?;
+            }
+            for &(i, ref data) in &buffers.image_resets {
+                buffers.images[i].write(data)?;
+            }
             // Event for timing
             let mut kernel_event = Event::empty();
-            kernel.cmd().enew(&mut kernel_event).enq().unwrap();
-            kernel_event.clone().wait().unwrap();
+            kernel.cmd().enew(&mut kernel_event).enq().map_err(|e| e.to_string())?;
+            kernel_event.clone().wait().map_err(|e| e.to_string())?;
             let command_start: u64 = kernel_event
                 .profiling_info(ProfilingInfo::Start)
                 .time()
-                .unwrap();
+                .map_err(|e| e.to_string())?;
             let command_end: u64 = kernel_event
                 .profiling_info(ProfilingInfo::End)
                 .time()
-                .unwrap();
+                .map_err(|e| e.to_string())?;
             let time = command_end - command_start;
             times.push(Duration::new(time / 1000000000, (time % 1000000000) as u32));
         }
-        times.iter().sum::<Duration>() / (runs as u32)
+        let mean = times.iter().sum::<Duration>() / times.len() as u32;
+        Ok(Status::Ok(times, mean, kernel_limits))
     }
 
-    fn print_header(parameters: &[(String, Vec<i32>)]) {
-        for &(ref k, _) in parameters {
-            if k.len() > 8 {
-                print!("|{:^8}", &k[0..8]);
-            } else {
-                print!("|{:^8}", k);
+    /// Compiles the kernel for a configuration and sets its work sizes and arguments.
+    fn build_kernel(&self, wrapper: &KernelWrapper, config: &Config, buffers: &Buffers,
+                    gws: SpatialDims, lws: SpatialDims)
+                    -> Result<(Kernel, KernelLimits), String> {
+        // Build the program with all defines
+        let mut program = Program::builder();
+        for (k, v) in config.iter() {
+            program = program.cmplr_opt(format!("-D {}={}", k, v.define()));
+        }
+        let program = program
+            .devices(self.device)
+            .src(wrapper.src.clone())
+            .build(&self.context)
+            .map_err(|e| e.to_string())?;
+
+        // Make kernel
+        let mut kernel = Kernel::new(wrapper.name.clone(), &program)
+            .map_err(|e| e.to_string())?
+            .queue(self.queue.clone())
+            .gws(gws)
+            .lws(lws);
+
+        let kernel_limits = KernelLimits::new(&kernel, &self.device);
+        if self.verbose {
+            println!("GWS: {:?}, LWS: {:?}", gws, lws);
+        }
+        // Add arguments
+        if let Some(ref arg_list) = wrapper.args {
+            kernel = args::set_args(kernel,
+                                    arg_list,
+                                    &buffers.buffers,
+                                    &buffers.dims,
+                                    &buffers.images,
+                                    &buffers.samplers,
+                                    config)?;
+        } else {
+            for &i in &wrapper.scalar_inputs {
+                kernel = kernel.arg_scl(i);
+            }
+            for buffer in &buffers.buffers {
+                kernel = kernel.arg_buf(buffer);
             }
         }
-        println!("|{:^13}|", "Time(s.ns)");
-        let l = 9 * parameters.len() + 15;
-        println!("{}", (0..l).map(|_| "-").collect::<String>());
+        Ok((kernel, kernel_limits))
     }
 
-    fn write_header(parameters: &[(String, Vec<i32>)], f: &mut Write) -> ::std::io::Result<()> {
-        for &(ref k, _) in parameters {
-            if k.len() > 8 {
-                write!(f, "{:^8}, ", &k[0..8])?;
-            } else {
-                write!(f, "{:^8}, ", k)?;
+    /// The bytes of local memory a configuration needs: the static local memory of the kernel
+    /// plus the `__local` arguments, which share the same limit.
+    fn local_memory_needed(wrapper: &KernelWrapper, params: &ParameterSet, config: &Config)
+                           -> Result<u64, String> {
+        let needed = match params.local_memory_needed {
+            Some(ref local_memory) => local_memory.call(config)?,
+            None => 0,
+        };
+        if needed < 0 {
+            return Err(format!("The local memory needed {} is negative.", needed));
+        }
+        Ok(needed as u64 + wrapper.local_args_size(config)?)
+    }
+
+    /// Checks the work group size against `CL_KERNEL_WORK_GROUP_SIZE`, which depends on the
+    /// resources of the compiled kernel and can be below the limit of the device.
+    fn check_kernel_limits(lws: &SpatialDims, kernel_limits: &KernelLimits) -> Result<(), String> {
+        let local_size = Tuner::dims(lws).iter().product::<usize>();
+        if local_size > kernel_limits.work_group_size {
+            return Err(format!("Work group size exceeds maximum for the kernel - {}/{}.",
+                               local_size,
+                               kernel_limits.work_group_size));
+        }
+        Ok(())
+    }
+
+    fn check_work_group_size(lws: &SpatialDims, work_group_limit: usize,
+                             work_item_limits: &[usize])
+                             -> Result<(), String> {
+        let dims = Tuner::dims(lws);
+        let local_size = dims.iter().product::<usize>();
+        if local_size > work_group_limit {
+            return Err(format!("Work group size exceeds maximum for the device - {}/{}.",
+                               local_size,
+                               work_group_limit));
+        }
+        for (i, (&size, &limit)) in dims.iter().zip(work_item_limits.iter()).enumerate() {
+            if size > limit {
+                return Err(format!("Work items in dimension {} exceed maximum for the device - \
+                                    {}/{}.",
+                                   i,
+                                   size,
+                                   limit));
             }
         }
-        writeln!(f, "{:^13}", "Time(s.ns)")
+        Ok(())
     }
 
-    fn print_parameters(parameters: &[i32], time: Duration) {
-        // Print time
-        for value in parameters {
-            print!("|{:>8}", value);
+    fn dims(dims: &SpatialDims) -> Vec<usize> {
+        match *dims {
+            SpatialDims::Unspecified => vec![],
+            SpatialDims::One(x) => vec![x],
+            SpatialDims::Two(x, y) => vec![x, y],
+            SpatialDims::Three(x, y, z) => vec![x, y, z],
         }
-        println!("|{:>3}.{:<09}|", time.as_secs(), time.subsec_nanos());
     }
 
-    fn write_parameters(parameters: &[i32], time: Duration, f: &mut Write)
-                        -> ::std::io::Result<()> {
-        // Print time
-        for value in parameters {
-            write!(f, "{:>8}, ", value)?;
+    fn spatial_dims(dims: &[usize]) -> Result<SpatialDims, String> {
+        match dims.len() {
+            1 => Ok(SpatialDims::One(dims[0])),
+            2 => Ok(SpatialDims::Two(dims[0], dims[1])),
+            3 => Ok(SpatialDims::Three(dims[0], dims[1], dims[2])),
+            n => Err(format!("Work sizes must have 1, 2 or 3 dimensions, not {}.", n)),
         }
-        writeln!(f, "{:>3}.{:<09}", time.as_secs(), time.subsec_nanos())
     }
 
-    fn calculate_work_sizes(wrapper: &KernelWrapper, params: &ParameterSet,
-                            config: &HashMap<String, i32>)
-                            -> (SpatialDims, SpatialDims) {
-        let mut global_size = wrapper.global_base;
-        let mut local_size = wrapper.local_base;
-        if global_size.dim_count() != local_size.dim_count() {
-            panic!("Different number of dimensions of global_size and local_size.")
+    fn calculate_work_sizes(wrapper: &KernelWrapper, params: &ParameterSet, config: &Config)
+                            -> Result<(SpatialDims, SpatialDims), String> {
+        let lists = if params.global_size.is_none() || params.local_size.is_none() {
+            Some(Tuner::calculate_list_work_sizes(wrapper, params, config)?)
+        } else {
+            None
+        };
+        let global_size = match (&params.global_size, lists) {
+            (Some(sizes), _) => Tuner::apply_work_size(sizes, &wrapper.global_base, config)?,
+            (None, Some((global_size, _))) => global_size,
+            (None, None) => unreachable!(),
+        };
+        let local_size = match (&params.local_size, lists) {
+            (Some(sizes), _) => Tuner::apply_work_size(sizes, &wrapper.local_base, config)?,
+            (None, Some((_, local_size))) => local_size,
+            (None, None) => unreachable!(),
+        };
+        Ok((global_size, local_size))
+    }
+
+    fn apply_work_size(sizes: &[WorkSizeFn], base: &SpatialDims, config: &Config)
+                       -> Result<SpatialDims, String> {
+        let base = Tuner::dims(base);
+        if sizes.len() != base.len() {
+            return Err(format!("There are {} work size functions for {} dimensions.",
+                               sizes.len(),
+                               base.len()));
         }
-        if let Some(ref mul) = params.mul_global_size {
-            if mul.len() != global_size.dim_count() as usize {
-                panic!("Different number of multipliers for global_size")
-            }
-            let mul: Vec<i32> = mul.iter()
-                .map(|x| match *x {
-                         Some(ref key) => config[key],
-                         None => 1,
-                     })
-                .collect();
-            global_size = match global_size {
-                SpatialDims::Unspecified => panic!("Unspecified spatial dims"),
-                SpatialDims::One(x) => SpatialDims::One(x * mul[0] as usize),
-                SpatialDims::Two(x, y) => {
-                    SpatialDims::Two(x * mul[0] as usize, y * mul[1] as usize)
-                }
-                SpatialDims::Three(x, y, z) => {
-                    SpatialDims::Three(x * mul[0] as usize,
-                                       y * mul[1] as usize,
-                                       z * mul[2] as usize)
-                }
-            }
+        let dims = sizes
+            .iter()
+            .zip(base.iter())
+            .map(|(f, &b)| f(b, config))
+            .collect::<Result<Vec<usize>, String>>()?;
+        Tuner::spatial_dims(&dims)
+    }
+
+    fn calculate_list_work_sizes(wrapper: &KernelWrapper, params: &ParameterSet, config: &Config)
+                                 -> Result<(SpatialDims, SpatialDims), String> {
+        let mut global_size = Tuner::dims(&wrapper.global_base);
+        let mut local_size = Tuner::dims(&wrapper.local_base);
+        if global_size.len() != local_size.len() {
+            return Err("Different number of dimensions of global_size and local_size.".into());
         }
-        if let Some(ref mul) = params.mul_local_size {
-            if mul.len() != local_size.dim_count() as usize {
-                panic!("Different number of multipliers for local_size")
-            }
-            let mul: Vec<i32> = mul.iter()
-                .map(|x| match *x {
-                         Some(ref key) => config[key],
-                         None => 1,
-                     })
-                .collect();
-            local_size = match local_size {
-                SpatialDims::Unspecified => panic!("Unspecified spatial dims"),
-                SpatialDims::One(x) => SpatialDims::One(x * mul[0] as usize),
-                SpatialDims::Two(x, y) => {
-                    SpatialDims::Two(x * mul[0] as usize, y * mul[1] as usize)
-                }
-                SpatialDims::Three(x, y, z) => {
-                    SpatialDims::Three(x * mul[0] as usize,
-                                       y * mul[1] as usize,
-                                       z * mul[2] as usize)
-                }
-            }
+        Tuner::scale(&mut global_size, &params.mul_global_size, "mul_global_size", config)?;
+        Tuner::scale(&mut local_size, &params.mul_local_size, "mul_local_size", config)?;
+        Tuner::scale(&mut global_size, &params.div_global_size, "div_global_size", config)?;
+        Ok((Tuner::spatial_dims(&global_size)?, Tuner::spatial_dims(&local_size)?))
+    }
+
+    /// Multiplies the dimensions by the parameters of a `mul_` list or divides them by those of
+    /// a `div_` list. The parameters must be positive.
+    fn scale(dims: &mut [usize], list: &Option<Vec<Option<String>>>, name: &str,
+             config: &Config)
+             -> Result<(), String> {
+        let list = match *list {
+            Some(ref list) => list,
+            None => return Ok(()),
+        };
+        if list.len() != dims.len() {
+            return Err(format!("{} has {} entries for {} dimensions.",
+                               name,
+                               list.len(),
+                               dims.len()));
         }
-        if let Some(ref div) = params.div_global_size {
-            if div.len() != global_size.dim_count() as usize {
-                panic!("Different number of multipliers for local_size")
-            }
-            let div: Vec<i32> = div.iter()
-                .map(|x| match *x {
-                         Some(ref key) => config[key],
-                         None => 1,
-                     })
-                .collect();
-            global_size = match global_size {
-                SpatialDims::Unspecified => panic!("Unspecified spatial dims"),
-                SpatialDims::One(x) => SpatialDims::One(x / div[0] as usize),
-                SpatialDims::Two(x, y) => {
-                    SpatialDims::Two(x / div[0] as usize, y / div[1] as usize)
+        for (dim, key) in dims.iter_mut().zip(list.iter()) {
+            let key = match *key {
+                Some(ref key) => key,
+                None => continue,
+            };
+            let factor = match config.get(key).and_then(Value::as_int) {
+                Some(x) if x > 0 => x as usize,
+                Some(x) => {
+                    return Err(format!("{} uses {} = {}, which is not positive.", name, key, x))
                 }
-                SpatialDims::Three(x, y, z) => {
-                    SpatialDims::Three(x / div[0] as usize,
-                                       y / div[1] as usize,
-                                       z / div[2] as usize)
-                }
-            }
+                None => return Err(format!("{} uses {}, which is not an integer.", name, key)),
+            };
+            *dim = if name.starts_with("div") {
+                *dim / factor
+            } else {
+                dim.checked_mul(factor)
+                    .ok_or_else(|| format!("Overflow in {}.", name))?
+            };
         }
-        (global_size, local_size)
+        Ok(())
     }
 }
 
+/// Integer division rounding up, e.g. for the number of work groups covering `x` items.
+pub fn ceil_div(x: usize, y: usize) -> usize {
+    x.div_ceil(y)
+}
+
+/// Rounds `x` up to the nearest multiple of `multiple`.
+pub fn round_up(x: usize, multiple: usize) -> usize {
+    ceil_div(x, multiple) * multiple
+}
+
 type TypeFn<T> = fn(&[i32]) -> T;
 
-pub struct FnWrap<'a, T> {
-    pub func: TypeFn<T>,
-    pub args: Vec<&'a str>,
+/// A function of the configuration, used for constraints and the local memory hook.
+pub struct FnWrap<T> {
+    func: Arc<dyn Fn(&Config) -> T + Send + Sync>,
+    args: Vec<String>,
 }
 
-impl<'a, T> Clone for FnWrap<'a, T> {
+impl<T> FnWrap<T> {
+    pub fn new<F>(func: F) -> Self
+        where F: Fn(&Config) -> T + Send + Sync + 'static
+    {
+        FnWrap {
+            func: Arc::new(func),
+            args: Vec::new(),
+        }
+    }
+
+    /// Records the names of the parameters the function uses, so that they can be checked by
+    /// `ParameterSet::validate`.
+    pub fn uses(mut self, args: Vec<String>) -> Self {
+        self.args = args;
+        self
+    }
+
+    /// The names of the parameters the function uses, if known.
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Wraps a function taking the values of the named parameters in the order of `args`.
+    pub fn with_args(func: TypeFn<T>, args: Vec<&str>) -> Self
+        where T: 'static
+    {
+        let args: Vec<String> = args.into_iter().map(|x| x.into()).collect();
+        let names = args.clone();
+        FnWrap::new(move |config: &Config| {
+                        let values: Vec<i32> = args.iter().map(|x| config.int(x)).collect();
+                        func(&values)
+                    })
+                .uses(names)
+    }
+
+    pub fn call(&self, config: &Config) -> T {
+        (self.func)(config)
+    }
+}
+
+impl<T> fmt::Debug for FnWrap<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FnWrap({:?})", self.args)
+    }
+}
+
+impl<T> Clone for FnWrap<T> {
     fn clone(&self) -> Self {
         FnWrap {
-            func: self.func,
+            func: self.func.clone(),
             args: self.args.clone(),
         }
     }
@@ -383,3 +1563,357 @@ impl<'a, T> Clone for FnWrap<'a, T> {
 pub fn is_power_of_two(value: &usize) -> bool {
     value & (value - 1) == 0
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn params() -> ParameterSet {
+        ParameterSetBuilder::new()
+            .param("A", vec![1, 2, 3])
+            .param("B", vec![1, 2, 3, 4])
+            .condition("B", "A == 2")
+            .build()
+            .unwrap()
+    }
+
+    /// A 2-D wrapper over a 1024x1024 global base and an 8x8 local base.
+    fn wrapper() -> KernelWrapper {
+        KernelWrapper {
+            scalar_inputs: Vec::new(),
+            inputs_dims: vec![vec![1024, 1024]],
+            inputs_padding: Vec::new(),
+            inputs_init: Vec::new(),
+            inputs_access: vec![Access::Output],
+            src: String::new(),
+            name: "kernel".into(),
+            problem_size: Vec::new(),
+            ref_name: None,
+            global_base: SpatialDims::Two(1024, 1024),
+            local_base: SpatialDims::Two(8, 8),
+            args: None,
+            images: Vec::new(),
+            flops: None,
+            bytes: None,
+        }
+    }
+
+    #[test]
+    fn typed_values() {
+        let values = vec![Value::from(8), Value::from(true), Value::from("half")];
+        assert_eq!(values.iter().map(Value::as_int).collect::<Vec<_>>(),
+                   vec![Some(8), Some(1), None]);
+        assert_eq!(values.iter().map(Value::define).collect::<Vec<_>>(),
+                   vec!["8", "1", "half"]);
+        assert_eq!(format!("{:>5}|{:<5}|{}", values[0], values[1], values[2]),
+                   "    8|true |half");
+        let json = ::serde_json::to_string(&values).unwrap();
+        assert_eq!(json, r#"[8,true,"half"]"#);
+        assert_eq!(::serde_json::from_str::<Vec<Value>>(&json).unwrap(), values);
+
+        // Booleans count as integers in expressions, strings do not
+        let params = ParameterSetBuilder::new()
+            .param("FAST", vec![false, true])
+            .param("TYPE", vec!["float", "half"])
+            .constraint("FAST || 0")
+            .build()
+            .unwrap();
+        let config = params.config(&[1, 1]).unwrap();
+        assert!(params.constraints[0].call(&config));
+        assert_eq!(config["TYPE"], Value::Str("half".into()));
+        assert!(!params.constraints[0].call(&params.config(&[0, 0]).unwrap()));
+    }
+
+    #[test]
+    fn inactive_parameters_take_their_default() {
+        let params = ParameterSetBuilder::new()
+            .param("SA", vec![0, 1])
+            .param("KWG", vec![8, 16])
+            .param("MDIMA", vec![16, 32])
+            .condition_default("MDIMA", "SA == 1", 0)
+            .derived("TILE", "MDIMA * 2")
+            .build()
+            .unwrap();
+        assert!(params.is_canonical(&[0, 1, 0]));
+        assert!(!params.is_canonical(&[0, 1, 1]));
+        assert!(params.is_canonical(&[1, 1, 1]));
+        let config = params.config(&[0, 1, 0]).unwrap();
+        assert_eq!(config["MDIMA"], Value::Int(0));
+        assert_eq!(config["TILE"], Value::Int(0));
+        assert_eq!(params.config(&[1, 0, 1]).unwrap()["MDIMA"], Value::Int(32));
+        let count = Configurations::new(&params, Strategy::Full).unwrap().count();
+        assert_eq!(count, 2 + 4);
+
+        // Conditions may only use the parameters before them
+        let later = ParameterSetBuilder::new()
+            .param("A", vec![1, 2])
+            .param("B", vec![1, 2])
+            .condition("A", "B == 1")
+            .build();
+        assert!(later.is_err());
+    }
+
+    #[test]
+    fn validate_reports_every_problem() {
+        let problems = ParameterSetBuilder::new()
+            .param("A", vec![1, 2])
+            .derived("A", "B * 2")
+            .param("B", vec![0, 1])
+            .param("C", vec![-2, 2])
+            .param("E", Vec::<i32>::new())
+            .constraint("D > 1")
+            .div_global_size(vec![Some("B"), Some("C")])
+            .build()
+            .err()
+            .unwrap();
+        let problems: Vec<&str> = problems.lines().collect();
+        assert!(problems.contains(&"Parameter A is declared more than once."));
+        assert!(problems.contains(&"Parameter E has no values."));
+        assert!(problems.contains(&"div_global_size uses parameter B which can be 0."));
+        assert!(problems.contains(&"div_global_size uses parameter C which can be -2."));
+        assert!(problems.iter().any(|p| p.contains("D")));
+    }
+
+    #[test]
+    fn validate_requires_an_access_per_buffer() {
+        let mut wrapper = wrapper();
+        assert!(params().validate(Some(&wrapper)).is_ok());
+        wrapper.inputs_dims.push(vec![16]);
+        assert_eq!(params().validate(Some(&wrapper)).err(),
+                   Some("The kernel has 2 buffers but inputs_access has 1 entries.".to_string()));
+        wrapper.inputs_access.push(Access::InOut);
+        assert!(params().validate(Some(&wrapper)).is_ok());
+        assert_eq!(wrapper.access(1), Access::InOut);
+    }
+
+    #[test]
+    fn local_arguments_count_toward_local_memory() {
+        let params = ParameterSetBuilder::new()
+            .param("WGS", vec![16, -16])
+            .local_memory("WGS * 8")
+            .build()
+            .unwrap();
+        let mut wrapper = wrapper();
+        let config = params.config(&[0]).unwrap();
+        assert_eq!(Tuner::local_memory_needed(&wrapper, &params, &config), Ok(128));
+        wrapper.args = Some(vec![Arg::Buffer(0),
+                                 Arg::local("WGS", 4, &["WGS"]).unwrap(),
+                                 Arg::Null,
+                                 Arg::local("WGS * 2", 2, &["WGS"]).unwrap()]);
+        assert_eq!(wrapper.local_args_size(&config), Ok(128));
+        assert_eq!(Tuner::local_memory_needed(&wrapper, &params, &config), Ok(256));
+        let config = params.config(&[1]).unwrap();
+        assert!(Tuner::local_memory_needed(&wrapper, &params, &config).is_err());
+        wrapper.args = None;
+        assert!(Tuner::local_memory_needed(&wrapper, &params, &config).is_err());
+    }
+
+    #[test]
+    fn validate_rejects_buffers_without_dimensions() {
+        let mut wrapper = wrapper();
+        wrapper.inputs_dims.push(Vec::new());
+        wrapper.inputs_access.push(Access::Input);
+        assert_eq!(params().validate(Some(&wrapper)).err(),
+                   Some("Buffer 1 has no dimensions.".to_string()));
+    }
+
+    #[test]
+    fn validate_checks_condition_defaults_of_work_size_parameters() {
+        let mut params = ParameterSetBuilder::new()
+            .param("A", vec![1, 2])
+            .param("B", vec![4, 8])
+            .mul_local_size(vec![Some("B"), None])
+            .build()
+            .unwrap();
+        params
+            .add_condition("B", "A == 2", Value::Int(0))
+            .unwrap();
+        assert_eq!(params.validate(None),
+                   Err("mul_local_size uses parameter B which can be 0.".into()));
+    }
+
+    #[test]
+    fn list_work_sizes_reject_non_positive_factors() {
+        let params = ParameterSetBuilder::new()
+            .param("A", vec![4])
+            .mul_global_size(vec![Some("A"), None])
+            .div_global_size(vec![None, Some("A")])
+            .build()
+            .unwrap();
+        let mut config = params.config(&[0]).unwrap();
+        let (global, local) = Tuner::calculate_work_sizes(&wrapper(), &params, &config).unwrap();
+        assert_eq!(Tuner::dims(&global), vec![4096, 256]);
+        assert_eq!(Tuner::dims(&local), vec![8, 8]);
+        config.insert("A".into(), Value::Int(-1));
+        assert_eq!(Tuner::calculate_work_sizes(&wrapper(), &params, &config).err(),
+                   Some("mul_global_size uses A = -1, which is not positive.".into()));
+    }
+
+    #[test]
+    fn expression_work_sizes() {
+        let params = ParameterSetBuilder::new()
+            .param("WGS", vec![16, 64])
+            .param("WPT", vec![3])
+            .mul_local_size(vec![Some("WGS"), None])
+            .global_size(vec!["round_up(ceil_div(1000, WPT), WGS)", "1"])
+            .build()
+            .unwrap();
+        let config = params.config(&[1, 0]).unwrap();
+        let (global, local) = Tuner::calculate_work_sizes(&wrapper(), &params, &config).unwrap();
+        // The global size replaces the lists, while the local size still uses them
+        assert_eq!(Tuner::dims(&global), vec![384, 1]);
+        assert_eq!(Tuner::dims(&local), vec![512, 8]);
+
+        let params = ParameterSetBuilder::new()
+            .param("WGS", vec![16])
+            .global_size(vec!["WGS - 32", "1"])
+            .local_size(vec!["WGS", "1"])
+            .build()
+            .unwrap();
+        let config = params.config(&[0]).unwrap();
+        assert!(Tuner::calculate_work_sizes(&wrapper(), &params, &config).is_err());
+        let params = ParameterSetBuilder::new()
+            .param("WGS", vec![16])
+            .global_size(vec!["WGS"])
+            .local_size(vec!["WGS", "1"])
+            .build()
+            .unwrap();
+        let config = params.config(&[0]).unwrap();
+        assert_eq!(Tuner::calculate_work_sizes(&wrapper(), &params, &config).err(),
+                   Some("There are 1 work size functions for 2 dimensions.".into()));
+    }
+
+    #[test]
+    fn constraints_are_closures() {
+        // A runtime value captured by the constraint, e.g. a limit queried from the device
+        let limit = 3;
+        let params = ParameterSetBuilder::new()
+            .param("A", vec![1, 2, 3])
+            .param("B", vec![1, 2, 3, 4])
+            .constraint_fn(FnWrap::new(move |c: &Config| c.int("A") * c.int("B") <= limit)
+                               .uses(vec!["A".into(), "B".into()]))
+            .constraint_fn(FnWrap::with_args(|v| v[0] != v[1], vec!["A", "B"]))
+            .build()
+            .unwrap();
+        let valid: Vec<Vec<usize>> = Configurations::new(&params, Strategy::Full)
+            .unwrap()
+            .filter(|indexes| {
+                        let config = params.config(indexes).unwrap();
+                        params.constraints.iter().all(|c| c.call(&config))
+                    })
+            .collect();
+        assert_eq!(valid, vec![vec![0, 1], vec![0, 2], vec![1, 0], vec![2, 0]]);
+        let copy = params.constraints[0].clone();
+        assert!(copy.call(&params.config(&[0, 2]).unwrap()));
+
+        let unknown = ParameterSetBuilder::new()
+            .param("A", vec![1])
+            .constraint_fn(FnWrap::new(|_: &Config| true).uses(vec!["C".into()]))
+            .build();
+        assert!(unknown.is_err());
+    }
+
+    #[test]
+    fn derived_divisors_of_zero_skip_the_configuration() {
+        let params = ParameterSetBuilder::new()
+            .param("MWG", vec![64, 128])
+            .derived("MDIV", "MWG - 64")
+            .div_global_size(vec![Some("MDIV"), None])
+            .build()
+            .unwrap();
+        assert_eq!(params.config(&[0]).err(),
+                   Some("Derived parameter MDIV is 0 but is used in div_global_size.".into()));
+        let config = params.config(&[1]).unwrap();
+        let (global, _) = Tuner::calculate_work_sizes(&wrapper(), &params, &config).unwrap();
+        assert_eq!(Tuner::dims(&global), vec![16, 1024]);
+    }
+
+    #[test]
+    fn full_configurations_skip_inactive_duplicates() {
+        let params = params();
+        let configurations: Vec<Vec<usize>> = Configurations::new(&params, Strategy::Full)
+            .unwrap()
+            .collect();
+        assert_eq!(configurations,
+                   vec![vec![0, 0], vec![1, 0], vec![1, 1], vec![1, 2], vec![1, 3], vec![2, 0]]);
+    }
+
+    #[test]
+    fn random_configurations_are_a_permutation() {
+        let params = params();
+        for _ in 0..20 {
+            let mut configurations: Vec<Vec<usize>> =
+                Configurations::new(&params, Strategy::Random).unwrap().collect();
+            configurations.sort();
+            assert_eq!(configurations,
+                       Configurations::new(&params, Strategy::Full)
+                           .unwrap()
+                           .collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn work_group_limits() {
+        let lws = SpatialDims::Two(16, 32);
+        assert!(Tuner::check_work_group_size(&lws, 512, &[1024, 1024, 64]).is_ok());
+        let e = Tuner::check_work_group_size(&lws, 256, &[1024, 1024, 64]).err().unwrap();
+        assert_eq!(e, "Work group size exceeds maximum for the device - 512/256.");
+        let e = Tuner::check_work_group_size(&lws, 512, &[1024, 16, 64]).err().unwrap();
+        assert_eq!(e, "Work items in dimension 1 exceed maximum for the device - 32/16.");
+
+        // The dummy kernel allows 256 work items, below the device limit
+        assert!(Tuner::check_kernel_limits(&SpatialDims::Two(16, 16), &KernelLimits::dummy())
+                    .is_ok());
+        let e = Tuner::check_kernel_limits(&lws, &KernelLimits::dummy()).err().unwrap();
+        assert_eq!(e, "Work group size exceeds maximum for the kernel - 512/256.");
+    }
+
+    /// A parameter set with `n` configurations.
+    fn single(n: usize) -> ParameterSet {
+        ParameterSetBuilder::new().param("A", (0..n as i32).collect()).build().unwrap()
+    }
+
+    #[test]
+    fn random_positions_are_a_bijection() {
+        for &n in &[1, 2, 3, 4, 5, 17, 64, 100, 1000] {
+            let params = single(n);
+            let keys = vec![1, 2, 3, 4, 5, 6];
+            let configurations = Configurations::with_keys(&params, keys).unwrap();
+            let mut positions: Vec<u64> = (0..n as u64)
+                .map(|i| configurations.permute(i))
+                .collect();
+            positions.sort();
+            assert_eq!(positions, (0..n as u64).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn random_orders_are_not_affine() {
+        // An affine order `(a * n + b) % total` has a constant step between positions and only
+        // reaches 8 of the 24 orders of 4 configurations
+        let params = single(1000);
+        let configurations = Configurations::new(&params, Strategy::Random).unwrap();
+        let steps: HashSet<u64> = (0..999)
+            .map(|i| (1000 + configurations.permute(i + 1) - configurations.permute(i)) % 1000)
+            .collect();
+        assert!(steps.len() > 100, "{} distinct steps", steps.len());
+
+        let params = single(4);
+        let orders: HashSet<Vec<Vec<usize>>> = (0..2000)
+            .map(|_| Configurations::new(&params, Strategy::Random).unwrap().collect())
+            .collect();
+        assert_eq!(orders.len(), 24);
+    }
+
+    #[test]
+    fn too_many_configurations() {
+        let mut builder = ParameterSetBuilder::new();
+        for name in &["A", "B", "C", "D", "E"] {
+            builder = builder.param(name, (0..10_000).collect());
+        }
+        let params = builder.build().unwrap();
+        assert!(Configurations::new(&params, Strategy::Random).is_err());
+    }
+}
@@ -0,0 +1,337 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use rand::{thread_rng, Rng, SeedableRng, StdRng};
+use rand::distributions::{IndependentSample, Normal, Range};
+
+/// How the contents of an input buffer are generated before tuning.
+///
+/// Random initialisers without a seed get one drawn when tuning starts. The seeds actually
+/// used are recorded in the results, so the same data can be generated again.
+#[derive(Clone)]
+pub enum Init {
+    Uniform {
+        seed: Option<u64>,
+        low: f32,
+        high: f32,
+    },
+    Normal {
+        seed: Option<u64>,
+        mean: f32,
+        std: f32,
+    },
+    Constant(f32),
+    Zeros,
+    /// The index of each element, i.e. `0, 1, 2, ...`.
+    Sequential,
+    /// A function from the index of each element to its value.
+    Closure(Arc<dyn Fn(usize) -> f32 + Send + Sync>),
+    /// Data supplied by the caller, which must have exactly the size of the buffer.
+    Host(Arc<Vec<f32>>),
+}
+
+impl Default for Init {
+    /// Uniform on `[0, 1)`.
+    fn default() -> Self {
+        Init::Uniform {
+            seed: None,
+            low: 0.0,
+            high: 1.0,
+        }
+    }
+}
+
+impl Init {
+    pub fn uniform(low: f32, high: f32) -> Self {
+        Init::Uniform {
+            seed: None,
+            low,
+            high,
+        }
+    }
+
+    pub fn normal(mean: f32, std: f32) -> Self {
+        Init::Normal {
+            seed: None,
+            mean,
+            std,
+        }
+    }
+
+    pub fn closure<F>(func: F) -> Self
+        where F: Fn(usize) -> f32 + Send + Sync + 'static
+    {
+        Init::Closure(Arc::new(func))
+    }
+
+    pub fn host(data: Vec<f32>) -> Self {
+        Init::Host(Arc::new(data))
+    }
+
+    /// Sets the seed of a random initialiser, other initialisers are returned unchanged.
+    pub fn seed(self, seed: u64) -> Self {
+        match self {
+            Init::Uniform { low, high, .. } => {
+                Init::Uniform {
+                    seed: Some(seed),
+                    low,
+                    high,
+                }
+            }
+            Init::Normal { mean, std, .. } => {
+                Init::Normal {
+                    seed: Some(seed),
+                    mean,
+                    std,
+                }
+            }
+            init => init,
+        }
+    }
+
+    /// The same initialiser with a random seed drawn if it is random and has none.
+    pub fn seeded(self) -> Self {
+        match self {
+            Init::Uniform { seed: None, .. } |
+            Init::Normal { seed: None, .. } => self.seed(thread_rng().gen()),
+            init => init,
+        }
+    }
+
+    /// Checks that the bounds of a uniform initialiser are finite with `low < high`, and that
+    /// the standard deviation of a normal one is finite and positive.
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            Init::Uniform { low, high, .. } if !(low.is_finite() && high.is_finite() &&
+                                                 low < high) => {
+                Err(format!("The bounds of {} must be finite with low < high.", self))
+            }
+            Init::Normal { mean, std, .. } if !(mean.is_finite() && std.is_finite() &&
+                                                std > 0.0) => {
+                Err(format!("The standard deviation of {} must be finite and positive.", self))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Generates the contents of a buffer with `len` elements. Fails if the initialiser is
+    /// invalid, see `validate`, random without a seed, see `seeded`, or host data of the wrong
+    /// size.
+    pub fn generate(&self, len: usize) -> Result<Vec<f32>, String> {
+        self.validate()?;
+        Ok(match *self {
+               Init::Uniform { seed, low, high } => {
+                   let mut rng = Init::rng(seed)?;
+                   let range = Range::new(low, high);
+                   (0..len).map(|_| range.ind_sample(&mut rng)).collect()
+               }
+               Init::Normal { seed, mean, std } => {
+                   let mut rng = Init::rng(seed)?;
+                   let normal = Normal::new(mean as f64, std as f64);
+                   (0..len).map(|_| normal.ind_sample(&mut rng) as f32).collect()
+               }
+               Init::Constant(x) => vec![x; len],
+               Init::Zeros => vec![0.0; len],
+               Init::Sequential => (0..len).map(|i| i as f32).collect(),
+               Init::Closure(ref func) => (0..len).map(|i| func(i)).collect(),
+               Init::Host(ref data) => {
+                   if data.len() != len {
+                       return Err(format!("Host data has {} elements but the buffer has {}.",
+                                          data.len(),
+                                          len));
+                   }
+                   data.as_ref().clone()
+               }
+           })
+    }
+
+    fn rng(seed: Option<u64>) -> Result<StdRng, String> {
+        let seed = seed.ok_or("Random initialisers must be seeded before generating data.")?;
+        Ok(StdRng::from_seed(&[seed as usize, (seed >> 32) as usize][..]))
+    }
+}
+
+impl fmt::Display for Init {
+    /// The form parsed by `from_str`, e.g. `uniform:-1:1:42` with the seed last, except for
+    /// closures and host data which are shown as `closure` and `host(<n> elements)`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let seed = |seed: Option<u64>| seed.map(|s| format!(":{}", s)).unwrap_or_default();
+        match *self {
+            Init::Uniform { seed: s, low, high } => {
+                write!(f, "uniform:{}:{}{}", low, high, seed(s))
+            }
+            Init::Normal { seed: s, mean, std } => {
+                write!(f, "normal:{}:{}{}", mean, std, seed(s))
+            }
+            Init::Constant(x) => write!(f, "constant:{}", x),
+            Init::Zeros => write!(f, "zeros"),
+            Init::Sequential => write!(f, "sequential"),
+            Init::Closure(_) => write!(f, "closure"),
+            Init::Host(ref data) => write!(f, "host({} elements)", data.len()),
+        }
+    }
+}
+
+impl fmt::Debug for Init {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl FromStr for Init {
+    type Err = String;
+
+    /// Parses `uniform`, `uniform:<low>:<high>[:<seed>]`, `normal`,
+    /// `normal:<mean>:<std>[:<seed>]`, `constant:<value>`, `zeros` or `sequential`, as written
+    /// by `Display`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let random = parts[0] == "uniform" || parts[0] == "normal";
+        let (numbers, seed) = match parts.len() {
+            4 if random => {
+                let seed = u64::from_str(parts[3])
+                    .map_err(|_| format!("Invalid seed '{}' in '{}'.", parts[3], s))?;
+                (&parts[1..3], Some(seed))
+            }
+            _ => (&parts[1..], None),
+        };
+        let numbers = numbers
+            .iter()
+            .map(|x| f32::from_str(x).map_err(|_| format!("Invalid number '{}' in '{}'.", x, s)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let init = match (parts[0], numbers.len()) {
+            ("uniform", 0) => Init::default(),
+            ("uniform", 2) => Init::uniform(numbers[0], numbers[1]),
+            ("normal", 0) => Init::normal(0.0, 1.0),
+            ("normal", 2) => Init::normal(numbers[0], numbers[1]),
+            ("constant", 1) => Init::Constant(numbers[0]),
+            ("zeros", 0) => Init::Zeros,
+            ("sequential", 0) => Init::Sequential,
+            _ => return Err(format!("Unknown initialiser '{}'.", s)),
+        };
+        init.validate()?;
+        Ok(match seed {
+               Some(seed) => init.seed(seed),
+               None => init,
+           })
+    }
+}
+
+/// Copies row-major `data` of shape `dims` into a buffer of shape `padded`, filling the
+/// padding with zeros.
+pub fn pad(data: &[f32], dims: &[usize], padded: &[usize]) -> Vec<f32> {
+    if dims == padded {
+        return data.to_vec();
+    }
+    let mut result = vec![0.0; padded.iter().product()];
+    let mut index = vec![0; dims.len()];
+    for &x in data {
+        let offset = index
+            .iter()
+            .zip(padded.iter())
+            .fold(0, |offset, (&i, &p)| offset * p + i);
+        result[offset] = x;
+        // Next index, the last dimension changing fastest
+        for d in (0..dims.len()).rev() {
+            index[d] += 1;
+            if index[d] < dims[d] {
+                break;
+            }
+            index[d] = 0;
+        }
+    }
+    result
+}
+
+/// Whether the kernel reads a buffer, writes it or both.
+///
+/// Output buffers are not initialised. In/out buffers are reset to their initial contents
+/// before every launch, so that kernels which accumulate into them take the same time on
+/// every run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Input,
+    Output,
+    InOut,
+}
+
+impl FromStr for Access {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "input" => Ok(Access::Input),
+            "output" => Ok(Access::Output),
+            "inout" => Ok(Access::InOut),
+            _ => {
+                Err(format!("Unknown buffer access '{}', expected 'input', 'output' or 'inout'.",
+                            s))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_round_trips() {
+        for init in &[Init::uniform(-1.0, 1.0).seed(42),
+                      Init::uniform(0.1, 0.25),
+                      Init::normal(0.5, 2.0).seed(u64::MAX),
+                      Init::Constant(-3.5),
+                      Init::Zeros,
+                      Init::Sequential] {
+            let parsed = Init::from_str(&init.to_string()).unwrap();
+            assert_eq!(parsed.to_string(), init.to_string());
+        }
+        assert_eq!(Init::from_str("uniform:-1:1:7").unwrap().to_string(), "uniform:-1:1:7");
+    }
+
+    #[test]
+    fn access_parses_every_mode() {
+        assert_eq!(Access::from_str("input"), Ok(Access::Input));
+        assert_eq!(Access::from_str("output"), Ok(Access::Output));
+        assert_eq!(Access::from_str("inout"), Ok(Access::InOut));
+        assert!(Access::from_str("in/out").is_err());
+        assert!(Access::from_str("").is_err());
+    }
+
+    #[test]
+    fn pad_keeps_every_element_in_place() {
+        let data: Vec<f32> = (1..7).map(|x| x as f32).collect();
+        assert_eq!(pad(&data, &[2, 3], &[2, 3]), data);
+        assert_eq!(pad(&data, &[6], &[8]), vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 0.0, 0.0]);
+        assert_eq!(pad(&data, &[2, 3], &[3, 4]),
+                   vec![1.0, 2.0, 3.0, 0.0, 4.0, 5.0, 6.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        // Aligned in the last dimension but not the first
+        assert_eq!(pad(&data, &[3, 2], &[4, 2]),
+                   vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 0.0, 0.0]);
+        assert_eq!(pad(&data, &[1, 2, 3], &[2, 2, 4]),
+                   vec![1.0, 2.0, 3.0, 0.0, 4.0, 5.0, 6.0, 0.0,
+                        0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        let data: Vec<f32> = (0..24).map(|x| x as f32).collect();
+        let padded = pad(&data, &[2, 3, 4], &[3, 3, 5]);
+        assert_eq!(padded.len(), 45);
+        // The last element, (1, 2, 3), with rows of 5 and planes of 15
+        assert_eq!(padded[15 + 2 * 5 + 3], 23.0);
+        assert_eq!(padded[4], 0.0);
+        assert_eq!(padded.iter().filter(|&&x| x != 0.0).count(), 23);
+    }
+
+    #[test]
+    fn rejects_invalid_parameters() {
+        assert!(Init::from_str("uniform:1:1").is_err());
+        assert!(Init::from_str("uniform:2:1").is_err());
+        assert!(Init::from_str("normal:0:0").is_err());
+        assert!(Init::from_str("normal:0:-1").is_err());
+        assert!(Init::from_str("uniform:0:1:-5").is_err());
+        assert!(Init::from_str("constant:1:2").is_err());
+        assert!(Init::uniform(1.0, 0.0).seed(1).generate(4).is_err());
+        assert!(Init::host(vec![1.0; 3]).generate(4).is_err());
+        assert!(Init::default().generate(4).is_err());
+        assert_eq!(Init::host(vec![1.0; 4]).generate(4), Ok(vec![1.0; 4]));
+    }
+}
@@ -0,0 +1,375 @@
+use std::str::FromStr;
+
+use ocl::{Platform, Device, Kernel};
+use ocl::flags::DeviceType;
+use ocl::enums::{DeviceInfo, DeviceInfoResult, KernelWorkGroupInfo, KernelWorkGroupInfoResult};
+
+/// Selects an OpenCL platform by index, name or vendor, or any platform, in which case
+/// devices are searched on every platform in order.
+#[derive(Clone, Debug, Default)]
+pub enum PlatformSelector {
+    #[default]
+    Any,
+    Index(usize),
+    Name(String),
+    Vendor(String),
+}
+
+/// Selects an OpenCL device by index, name, vendor, type or an arbitrary predicate.
+#[derive(Clone, Debug)]
+pub enum DeviceSelector {
+    Index(usize),
+    Name(String),
+    Vendor(String),
+    Type(DeviceType),
+    Predicate(fn(&Device) -> bool),
+}
+
+impl Default for DeviceSelector {
+    fn default() -> Self {
+        DeviceSelector::Index(0)
+    }
+}
+
+impl PlatformSelector {
+    pub fn matches(&self, index: usize, platform: &Platform) -> bool {
+        match *self {
+            PlatformSelector::Any => true,
+            PlatformSelector::Index(i) => i == index,
+            PlatformSelector::Name(ref name) => contains_ignore_case(&platform.name(), name),
+            PlatformSelector::Vendor(ref vendor) => {
+                contains_ignore_case(&platform.vendor(), vendor)
+            }
+        }
+    }
+
+    pub fn select(&self) -> Result<Platform, String> {
+        Platform::list()
+            .into_iter()
+            .enumerate()
+            .find(|&(i, ref p)| self.matches(i, p))
+            .map(|(_, p)| p)
+            .ok_or_else(|| format!("No platform matches {:?}.", self))
+    }
+
+    /// Returns the first device matching `device` on the platforms matching the selector, in
+    /// order, with its platform.
+    pub fn select_device(&self, device: &DeviceSelector) -> Result<(Platform, Device), String> {
+        self.select_devices(device)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| {
+                            format!("No device on a platform matching {:?} matches {:?}.",
+                                    self,
+                                    device)
+                        })
+    }
+
+    /// Returns all devices matching `device` on the platforms matching the selector, with their
+    /// platforms.
+    pub fn select_devices(&self, device: &DeviceSelector)
+                          -> Result<Vec<(Platform, Device)>, String> {
+        let mut devices = Vec::new();
+        for (i, platform) in Platform::list().into_iter().enumerate() {
+            if self.matches(i, &platform) {
+                devices.extend(device.select_all(&platform)?.into_iter().map(|d| (platform, d)));
+            }
+        }
+        Ok(devices)
+    }
+}
+
+impl DeviceSelector {
+    pub fn matches(&self, index: usize, device: &Device) -> bool {
+        match *self {
+            DeviceSelector::Index(i) => i == index,
+            DeviceSelector::Name(ref name) => contains_ignore_case(&device.name(), name),
+            DeviceSelector::Vendor(ref vendor) => contains_ignore_case(&device.vendor(), vendor),
+            DeviceSelector::Type(device_type) => {
+                match device.info(DeviceInfo::Type) {
+                    DeviceInfoResult::Type(t) => t.contains(device_type),
+                    _ => unreachable!(),
+                }
+            }
+            DeviceSelector::Predicate(func) => func(device),
+        }
+    }
+
+    /// Returns the first device on the platform matching the selector.
+    pub fn select(&self, platform: &Platform) -> Result<Device, String> {
+        self.select_all(platform)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| {
+                            format!("No device on platform '{}' matches {:?}.",
+                                    platform.name(),
+                                    self)
+                        })
+    }
+
+    /// Returns all devices on the platform matching the selector.
+    pub fn select_all(&self, platform: &Platform) -> Result<Vec<Device>, String> {
+        let devices = Device::list_all(platform).map_err(|e| e.to_string())?;
+        Ok(devices
+               .into_iter()
+               .enumerate()
+               .filter(|&(i, ref d)| self.matches(i, d))
+               .map(|(_, d)| d)
+               .collect())
+    }
+}
+
+impl FromStr for PlatformSelector {
+    type Err = String;
+
+    /// Parses `any`, `<index>`, `name:<substring>`, `vendor:<substring>` or a bare name
+    /// substring.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(i) = usize::from_str(s) {
+            return Ok(PlatformSelector::Index(i));
+        }
+        if s.eq_ignore_ascii_case("any") {
+            return Ok(PlatformSelector::Any);
+        }
+        match split_prefix(s) {
+            Some((prefix, "")) => Err(format!("Empty {} in platform selector '{}'.", prefix, s)),
+            Some(("name", rest)) => Ok(PlatformSelector::Name(rest.into())),
+            Some(("vendor", rest)) => Ok(PlatformSelector::Vendor(rest.into())),
+            Some((prefix, _)) => Err(format!("Unknown platform selector '{}'.", prefix)),
+            None if s.is_empty() => Err("Empty platform selector.".into()),
+            None => Ok(PlatformSelector::Name(s.into())),
+        }
+    }
+}
+
+impl FromStr for DeviceSelector {
+    type Err = String;
+
+    /// Parses `<index>`, `gpu`, `cpu`, `accelerator`, `name:<substring>`,
+    /// `vendor:<substring>` or a bare name substring.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(i) = usize::from_str(s) {
+            return Ok(DeviceSelector::Index(i));
+        }
+        match s.to_lowercase().as_str() {
+            "gpu" => return Ok(DeviceSelector::Type(DeviceType::new().gpu())),
+            "cpu" => return Ok(DeviceSelector::Type(DeviceType::new().cpu())),
+            "accelerator" => return Ok(DeviceSelector::Type(DeviceType::new().accelerator())),
+            _ => {}
+        }
+        match split_prefix(s) {
+            Some((prefix, "")) => Err(format!("Empty {} in device selector '{}'.", prefix, s)),
+            Some(("name", rest)) => Ok(DeviceSelector::Name(rest.into())),
+            Some(("vendor", rest)) => Ok(DeviceSelector::Vendor(rest.into())),
+            Some((prefix, _)) => Err(format!("Unknown device selector '{}'.", prefix)),
+            None if s.is_empty() => Err("Empty device selector.".into()),
+            None => Ok(DeviceSelector::Name(s.into())),
+        }
+    }
+}
+
+/// Identifies the device, driver and platform a tuning result was obtained on.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DeviceFingerprint {
+    pub platform: String,
+    pub platform_version: String,
+    pub device: String,
+    pub vendor: String,
+    pub device_type: String,
+    pub driver_version: String,
+    pub opencl_version: String,
+    pub compute_units: u32,
+    pub clock_frequency: u32,
+    pub global_memory: u64,
+    pub local_memory: u64,
+    pub max_work_group_size: usize,
+}
+
+impl DeviceFingerprint {
+    pub fn new(platform: &Platform, device: &Device) -> Self {
+        DeviceFingerprint {
+            platform: platform.name(),
+            platform_version: platform.version(),
+            device: device.name(),
+            vendor: device.vendor(),
+            device_type: device_type_name(device).into(),
+            driver_version: device.info(DeviceInfo::DriverVersion).to_string(),
+            opencl_version: device.info(DeviceInfo::Version).to_string(),
+            compute_units: match device.info(DeviceInfo::MaxComputeUnits) {
+                DeviceInfoResult::MaxComputeUnits(x) => x,
+                _ => unreachable!(),
+            },
+            clock_frequency: match device.info(DeviceInfo::MaxClockFrequency) {
+                DeviceInfoResult::MaxClockFrequency(x) => x,
+                _ => unreachable!(),
+            },
+            global_memory: match device.info(DeviceInfo::GlobalMemSize) {
+                DeviceInfoResult::GlobalMemSize(x) => x,
+                _ => unreachable!(),
+            },
+            local_memory: match device.info(DeviceInfo::LocalMemSize) {
+                DeviceInfoResult::LocalMemSize(x) => x,
+                _ => unreachable!(),
+            },
+            max_work_group_size: match device.info(DeviceInfo::MaxWorkGroupSize) {
+                DeviceInfoResult::MaxWorkGroupSize(x) => x,
+                _ => unreachable!(),
+            },
+        }
+    }
+
+    /// The fingerprint as ordered `(key, value)` pairs, used when writing results.
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        vec![("platform", self.platform.clone()),
+             ("platform_version", self.platform_version.clone()),
+             ("device", self.device.clone()),
+             ("vendor", self.vendor.clone()),
+             ("device_type", self.device_type.clone()),
+             ("driver_version", self.driver_version.clone()),
+             ("opencl_version", self.opencl_version.clone()),
+             ("compute_units", self.compute_units.to_string()),
+             ("clock_frequency", self.clock_frequency.to_string()),
+             ("global_memory", self.global_memory.to_string()),
+             ("local_memory", self.local_memory.to_string()),
+             ("max_work_group_size", self.max_work_group_size.to_string())]
+    }
+
+    /// A fingerprint for tests which run without a device.
+    #[cfg(test)]
+    pub fn dummy() -> Self {
+        DeviceFingerprint {
+            platform: "Platform".into(),
+            platform_version: "OpenCL 1.2".into(),
+            device: "Device".into(),
+            vendor: "Vendor".into(),
+            device_type: "GPU".into(),
+            driver_version: "1.0".into(),
+            opencl_version: "OpenCL 1.2".into(),
+            compute_units: 1,
+            clock_frequency: 1000,
+            global_memory: 1 << 30,
+            local_memory: 1 << 15,
+            max_work_group_size: 256,
+        }
+    }
+}
+
+/// Kernel specific limits reported by `clGetKernelWorkGroupInfo` for a compiled configuration.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct KernelLimits {
+    pub work_group_size: usize,
+    pub preferred_work_group_size_multiple: usize,
+    pub private_memory: u64,
+    pub local_memory: u64,
+}
+
+impl KernelLimits {
+    pub fn new(kernel: &Kernel, device: &Device) -> Self {
+        KernelLimits {
+            work_group_size: match kernel.wg_info(*device, KernelWorkGroupInfo::WorkGroupSize) {
+                KernelWorkGroupInfoResult::WorkGroupSize(x) => x,
+                _ => unreachable!(),
+            },
+            preferred_work_group_size_multiple:
+                match kernel.wg_info(*device, KernelWorkGroupInfo::PreferredWorkGroupSizeMultiple) {
+                    KernelWorkGroupInfoResult::PreferredWorkGroupSizeMultiple(x) => x,
+                    _ => unreachable!(),
+                },
+            private_memory: match kernel.wg_info(*device, KernelWorkGroupInfo::PrivateMemSize) {
+                KernelWorkGroupInfoResult::PrivateMemSize(x) => x,
+                _ => unreachable!(),
+            },
+            local_memory: match kernel.wg_info(*device, KernelWorkGroupInfo::LocalMemSize) {
+                KernelWorkGroupInfoResult::LocalMemSize(x) => x,
+                _ => unreachable!(),
+            },
+        }
+    }
+
+    /// Limits for tests which run without a device.
+    #[cfg(test)]
+    pub fn dummy() -> Self {
+        KernelLimits {
+            work_group_size: 256,
+            preferred_work_group_size_multiple: 32,
+            private_memory: 0,
+            local_memory: 0,
+        }
+    }
+}
+
+/// Returns every platform together with all of its devices.
+pub fn list_devices() -> Vec<(Platform, Vec<Device>)> {
+    Platform::list()
+        .into_iter()
+        .map(|p| {
+                 let devices = Device::list_all(&p).unwrap_or_default();
+                 (p, devices)
+             })
+        .collect()
+}
+
+/// Prints every platform and device with their key properties.
+pub fn print_devices() {
+    for (i, (platform, devices)) in list_devices().into_iter().enumerate() {
+        println!("Platform {}: {} by {} - {}",
+                 i,
+                 platform.name(),
+                 platform.vendor(),
+                 platform.version());
+        for (j, device) in devices.iter().enumerate() {
+            println!("    Device {}: {} by {}", j, device.name(), device.vendor());
+            println!("        Type:              {}", device_type_name(device));
+            println!("        Version:           {}", device.info(DeviceInfo::Version));
+            println!("        Driver:            {}", device.info(DeviceInfo::DriverVersion));
+            println!("        Compute units:     {}", device.info(DeviceInfo::MaxComputeUnits));
+            println!("        Clock (MHz):       {}", device.info(DeviceInfo::MaxClockFrequency));
+            println!("        Global memory:     {}", device.info(DeviceInfo::GlobalMemSize));
+            println!("        Local memory:      {}", device.info(DeviceInfo::LocalMemSize));
+            println!("        Max work group:    {}", device.info(DeviceInfo::MaxWorkGroupSize));
+        }
+    }
+}
+
+pub fn device_type_name(device: &Device) -> &'static str {
+    match device.info(DeviceInfo::Type) {
+        DeviceInfoResult::Type(t) => {
+            if t.contains(DeviceType::new().gpu()) {
+                "GPU"
+            } else if t.contains(DeviceType::new().cpu()) {
+                "CPU"
+            } else if t.contains(DeviceType::new().accelerator()) {
+                "Accelerator"
+            } else {
+                "Other"
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn split_prefix(s: &str) -> Option<(&str, &str)> {
+    s.find(':').map(|i| (&s[..i], &s[i + 1..]))
+}
+
+fn contains_ignore_case(haystack: &str, needle: &str) -> bool {
+    haystack.to_lowercase().contains(&needle.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selectors_reject_empty_substrings() {
+        assert!(PlatformSelector::from_str("name:").is_err());
+        assert!(PlatformSelector::from_str("vendor:").is_err());
+        assert!(DeviceSelector::from_str("name:").is_err());
+        assert!(DeviceSelector::from_str("vendor:").is_err());
+        assert!(DeviceSelector::from_str("").is_err());
+        match PlatformSelector::from_str("any") {
+            Ok(PlatformSelector::Any) => {}
+            other => panic!("Expected any, got {:?}", other),
+        }
+    }
+}
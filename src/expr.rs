@@ -0,0 +1,639 @@
+use std::fmt;
+use std::sync::Arc;
+
+use core::{Config, FnWrap, Formula, WorkSizeFn};
+
+/// An integer expression over parameter names, with C-like operators and precedence.
+///
+/// Supported are integer literals, parameter names, parentheses, the unary operators `-` and
+/// `!`, the binary operators `* / % + - < <= > >= == != && ||` and the functions `min(a, b)`,
+/// `max(a, b)`, `ceil_div(a, b)` and `round_up(a, b)`. Comparisons and logical operators
+/// evaluate to 1 or 0. Division truncates as in OpenCL C, while `ceil_div` rounds towards
+/// positive infinity for either sign. Dividing by zero, rounding up to a negative multiple and
+/// overflowing 64 bits are errors.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    Variable(String),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(Function, Vec<Expr>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Function {
+    Min,
+    Max,
+    CeilDiv,
+    RoundUp,
+}
+
+impl Expr {
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let tokens = tokenize(src)?;
+        let mut parser = Parser {
+            src,
+            tokens,
+            position: 0,
+        };
+        let expr = parser.parse_binary(0)?;
+        match parser.tokens.get(parser.position) {
+            None => Ok(expr),
+            Some(&(ref token, at)) => Err(parser.error(&format!("Unexpected '{}'", token), at)),
+        }
+    }
+
+    /// Parses the expression and checks that it only refers to the given names.
+    pub fn parse_with_names(src: &str, names: &[&str]) -> Result<Self, String> {
+        let expr = Expr::parse(src)?;
+        expr.check_names(names)?;
+        Ok(expr)
+    }
+
+    /// Checks that the expression only refers to the given names.
+    pub fn check_names(&self, names: &[&str]) -> Result<(), String> {
+        let unknown: Vec<String> = self.variables()
+            .into_iter()
+            .filter(|v| !names.contains(&v.as_str()))
+            .collect();
+        if unknown.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Unknown parameters {:?} in '{}'.", unknown, self))
+        }
+    }
+
+    /// Replaces the named variables with constants, e.g. the problem size.
+    pub fn substitute(&self, values: &[(String, usize)]) -> Expr {
+        match *self {
+            Expr::Number(x) => Expr::Number(x),
+            Expr::Variable(ref name) => {
+                match values.iter().find(|(k, _)| k == name) {
+                    Some(&(_, v)) => Expr::Number(v as i64),
+                    None => Expr::Variable(name.clone()),
+                }
+            }
+            Expr::Neg(ref e) => Expr::Neg(Box::new(e.substitute(values))),
+            Expr::Not(ref e) => Expr::Not(Box::new(e.substitute(values))),
+            Expr::Binary(op, ref l, ref r) => {
+                Expr::Binary(op, Box::new(l.substitute(values)), Box::new(r.substitute(values)))
+            }
+            Expr::Call(func, ref args) => {
+                Expr::Call(func, args.iter().map(|a| a.substitute(values)).collect())
+            }
+        }
+    }
+
+    /// A constraint which is violated when the expression evaluates to 0 or fails to
+    /// evaluate, e.g. because of a division by zero.
+    pub fn into_constraint(self) -> FnWrap<bool> {
+        let variables = self.variables();
+        FnWrap::new(move |config: &Config| self.eval(config).map(|x| x != 0).unwrap_or(false))
+            .uses(variables)
+    }
+
+    /// A formula which fails when the expression fails to evaluate or its value does not fit
+    /// in an `i32`.
+    pub fn into_formula(self) -> Formula {
+        let variables = self.variables();
+        FnWrap::new(move |config: &Config| self.eval_int(config)).uses(variables)
+    }
+
+    /// A work size which ignores the base size and evaluates the expression instead, see
+    /// `eval_size`.
+    pub fn into_work_size(self) -> WorkSizeFn {
+        Arc::new(move |_, config: &Config| self.eval_size(config))
+    }
+
+    /// All parameter names the expression refers to, without duplicates.
+    pub fn variables(&self) -> Vec<String> {
+        let mut variables = Vec::new();
+        self.collect_variables(&mut variables);
+        variables
+    }
+
+    fn collect_variables(&self, variables: &mut Vec<String>) {
+        match *self {
+            Expr::Number(_) => {}
+            Expr::Variable(ref name) => {
+                if !variables.contains(name) {
+                    variables.push(name.clone());
+                }
+            }
+            Expr::Neg(ref e) |
+            Expr::Not(ref e) => e.collect_variables(variables),
+            Expr::Binary(_, ref l, ref r) => {
+                l.collect_variables(variables);
+                r.collect_variables(variables);
+            }
+            Expr::Call(_, ref args) => {
+                for arg in args {
+                    arg.collect_variables(variables);
+                }
+            }
+        }
+    }
+
+    /// Evaluates the expression as an `i32`, failing if the value does not fit.
+    pub fn eval_int(&self, config: &Config) -> Result<i32, String> {
+        let x = self.eval(config)?;
+        if x < i32::MIN as i64 || x > i32::MAX as i64 {
+            return Err(format!("The value {} of {} does not fit in an int.", x, self));
+        }
+        Ok(x as i32)
+    }
+
+    /// Evaluates the expression as a size, failing if the value is negative.
+    pub fn eval_size(&self, config: &Config) -> Result<usize, String> {
+        let x = self.eval(config)?;
+        if x < 0 || x as u64 > usize::MAX as u64 {
+            return Err(format!("The value {} of {} is not a valid size.", x, self));
+        }
+        Ok(x as usize)
+    }
+
+    pub fn eval(&self, config: &Config) -> Result<i64, String> {
+        let overflow = || format!("Overflow in {}.", self);
+        match *self {
+            Expr::Number(x) => Ok(x),
+            Expr::Variable(ref name) => {
+                match config.get(name) {
+                    Some(value) => {
+                        value
+                            .as_int()
+                            .map(|x| x as i64)
+                            .ok_or_else(|| format!("Parameter {} is not an integer.", name))
+                    }
+                    None => Err(format!("Parameter {} does not exist.", name)),
+                }
+            }
+            Expr::Neg(ref e) => e.eval(config)?.checked_neg().ok_or_else(overflow),
+            Expr::Not(ref e) => Ok((e.eval(config)? == 0) as i64),
+            Expr::Binary(op, ref l, ref r) => {
+                let l = l.eval(config)?;
+                // Short circuit the logical operators
+                match op {
+                    BinaryOp::And if l == 0 => return Ok(0),
+                    BinaryOp::Or if l != 0 => return Ok(1),
+                    _ => {}
+                }
+                let r = r.eval(config)?;
+                match op {
+                    BinaryOp::Mul => l.checked_mul(r).ok_or_else(overflow),
+                    BinaryOp::Div if r == 0 => Err(format!("Division by zero in {}.", self)),
+                    BinaryOp::Div => l.checked_div(r).ok_or_else(overflow),
+                    BinaryOp::Rem if r == 0 => Err(format!("Modulo by zero in {}.", self)),
+                    BinaryOp::Rem => l.checked_rem(r).ok_or_else(overflow),
+                    BinaryOp::Add => l.checked_add(r).ok_or_else(overflow),
+                    BinaryOp::Sub => l.checked_sub(r).ok_or_else(overflow),
+                    BinaryOp::Lt => Ok((l < r) as i64),
+                    BinaryOp::Le => Ok((l <= r) as i64),
+                    BinaryOp::Gt => Ok((l > r) as i64),
+                    BinaryOp::Ge => Ok((l >= r) as i64),
+                    BinaryOp::Eq => Ok((l == r) as i64),
+                    BinaryOp::Ne => Ok((l != r) as i64),
+                    BinaryOp::And | BinaryOp::Or => Ok((r != 0) as i64),
+                }
+            }
+            Expr::Call(func, ref args) => {
+                let a = args[0].eval(config)?;
+                let b = args[1].eval(config)?;
+                match func {
+                    Function::Min => Ok(a.min(b)),
+                    Function::Max => Ok(a.max(b)),
+                    Function::CeilDiv | Function::RoundUp if b == 0 => {
+                        Err(format!("Division by zero in {}.", self))
+                    }
+                    Function::RoundUp if b < 0 => {
+                        Err(format!("Rounding up to a negative multiple in {}.", self))
+                    }
+                    Function::CeilDiv => ceil_div(a, b).ok_or_else(overflow),
+                    Function::RoundUp => {
+                        ceil_div(a, b).and_then(|x| x.checked_mul(b)).ok_or_else(overflow)
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expr::Number(x) => write!(f, "{}", x),
+            Expr::Variable(ref name) => write!(f, "{}", name),
+            Expr::Neg(ref e) => write!(f, "-{}", e),
+            Expr::Not(ref e) => write!(f, "!{}", e),
+            Expr::Binary(op, ref l, ref r) => write!(f, "({} {} {})", l, op.symbol(), r),
+            Expr::Call(func, ref args) => write!(f, "{}({}, {})", func.name(), args[0], args[1]),
+        }
+    }
+}
+
+impl BinaryOp {
+    fn from_symbol(symbol: &str) -> Option<BinaryOp> {
+        match symbol {
+            "*" => Some(BinaryOp::Mul),
+            "/" => Some(BinaryOp::Div),
+            "%" => Some(BinaryOp::Rem),
+            "+" => Some(BinaryOp::Add),
+            "-" => Some(BinaryOp::Sub),
+            "<" => Some(BinaryOp::Lt),
+            "<=" => Some(BinaryOp::Le),
+            ">" => Some(BinaryOp::Gt),
+            ">=" => Some(BinaryOp::Ge),
+            "==" => Some(BinaryOp::Eq),
+            "!=" => Some(BinaryOp::Ne),
+            "&&" => Some(BinaryOp::And),
+            "||" => Some(BinaryOp::Or),
+            _ => None,
+        }
+    }
+
+    fn symbol(&self) -> &'static str {
+        match *self {
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+        }
+    }
+
+    /// Binding strength, higher binds tighter.
+    fn precedence(&self) -> u8 {
+        match *self {
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 6,
+            BinaryOp::Add | BinaryOp::Sub => 5,
+            BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => 4,
+            BinaryOp::Eq | BinaryOp::Ne => 3,
+            BinaryOp::And => 2,
+            BinaryOp::Or => 1,
+        }
+    }
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Function> {
+        match name {
+            "min" => Some(Function::Min),
+            "max" => Some(Function::Max),
+            "ceil_div" => Some(Function::CeilDiv),
+            "round_up" => Some(Function::RoundUp),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            Function::Min => "min",
+            Function::Max => "max",
+            Function::CeilDiv => "ceil_div",
+            Function::RoundUp => "round_up",
+        }
+    }
+}
+
+/// `a / b` rounded towards positive infinity, or `None` on overflow. The truncated quotient
+/// is one too small when the exact one is positive and not an integer.
+fn ceil_div(a: i64, b: i64) -> Option<i64> {
+    let q = a.checked_div(b)?;
+    if a % b != 0 && (a < 0) == (b < 0) {
+        q.checked_add(1)
+    } else {
+        Some(q)
+    }
+}
+
+/// Parses a constraint such as `"KWG % KWI == 0"`, see `Expr::into_constraint`.
+pub fn constraint(src: &str, names: &[&str]) -> Result<FnWrap<bool>, String> {
+    Ok(Expr::parse_with_names(src, names)?.into_constraint())
+}
+
+/// Parses an integer formula such as the local memory needed by a configuration.
+pub fn formula(src: &str, names: &[&str]) -> Result<Formula, String> {
+    Ok(Expr::parse_with_names(src, names)?.into_formula())
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Op(String),
+    LParen,
+    RParen,
+    Comma,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Number(x) => write!(f, "{}", x),
+            Token::Ident(ref s) |
+            Token::Op(ref s) => write!(f, "{}", s),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::Comma => write!(f, ","),
+        }
+    }
+}
+
+/// The tokens of the source, each with the position of its first character.
+fn tokenize(src: &str) -> Result<Vec<(Token, usize)>, String> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            let digits: String = chars[start..i].iter().cloned().collect();
+            let value = digits
+                .parse()
+                .map_err(|_| {
+                             format!("Invalid number '{}' at position {} in '{}'.",
+                                     digits,
+                                     start,
+                                     src)
+                         })?;
+            tokens.push((Token::Number(value), start));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push((Token::Ident(chars[start..i].iter().cloned().collect()), start));
+        } else if c == '(' {
+            tokens.push((Token::LParen, i));
+            i += 1;
+        } else if c == ')' {
+            tokens.push((Token::RParen, i));
+            i += 1;
+        } else if c == ',' {
+            tokens.push((Token::Comma, i));
+            i += 1;
+        } else {
+            let two: String = chars[i..chars.len().min(i + 2)].iter().cloned().collect();
+            if ["<=", ">=", "==", "!=", "&&", "||"].contains(&two.as_str()) {
+                tokens.push((Token::Op(two), i));
+                i += 2;
+            } else if "*/%+-<>!".contains(c) {
+                tokens.push((Token::Op(c.to_string()), i));
+                i += 1;
+            } else {
+                return Err(format!("Unexpected character '{}' at position {} in '{}'.",
+                                   c,
+                                   i,
+                                   src));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    /// The next token and its position.
+    fn next(&mut self) -> Result<(Token, usize), String> {
+        match self.tokens.get(self.position).cloned() {
+            Some(token) => {
+                self.position += 1;
+                Ok(token)
+            }
+            None => {
+                Err(self.error("Unexpected end of expression", self.src.chars().count()))
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        let (token, at) = self.next()?;
+        if token == expected {
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected '{}' but found '{}'", expected, token), at))
+        }
+    }
+
+    fn error(&self, message: &str, at: usize) -> String {
+        format!("{} at position {} in '{}'.", message, at, self.src)
+    }
+
+    /// Precedence climbing over the binary operators binding tighter than `min_precedence`.
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<Expr, String> {
+        let mut lhs = self.parse_unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Op(symbol)) => BinaryOp::from_symbol(symbol),
+                _ => None,
+            };
+            match op {
+                Some(op) if op.precedence() > min_precedence => {
+                    self.position += 1;
+                    let rhs = self.parse_binary(op.precedence())?;
+                    lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+                }
+                _ => return Ok(lhs),
+            }
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        let (token, at) = self.next()?;
+        match token {
+            Token::Op(ref op) if op == "-" => Ok(Expr::Neg(Box::new(self.parse_unary()?))),
+            Token::Op(ref op) if op == "!" => Ok(Expr::Not(Box::new(self.parse_unary()?))),
+            Token::Number(x) => Ok(Expr::Number(x)),
+            Token::LParen => {
+                let expr = self.parse_binary(0)?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            }
+            Token::Ident(name) => {
+                if self.peek() != Some(&Token::LParen) {
+                    return Ok(Expr::Variable(name));
+                }
+                let func = Function::from_name(&name)
+                    .ok_or_else(|| self.error(&format!("Unknown function '{}'", name), at))?;
+                self.expect(Token::LParen)?;
+                let a = self.parse_binary(0)?;
+                self.expect(Token::Comma)?;
+                let b = self.parse_binary(0)?;
+                self.expect(Token::RParen)?;
+                Ok(Expr::Call(func, vec![a, b]))
+            }
+            token => Err(self.error(&format!("Unexpected '{}'", token), at)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::Value;
+
+    fn config(values: &[(&str, i32)]) -> Config {
+        let mut config = Config::new();
+        for &(k, v) in values {
+            config.insert(k.into(), Value::Int(v));
+        }
+        config
+    }
+
+    fn eval(src: &str) -> Result<i64, String> {
+        Expr::parse(src)?.eval(&config(&[("A", 6), ("B", 4), ("Z", 0)]))
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("10 - 4 - 3"), Ok(3));
+        assert_eq!(eval("64 / 4 / 2"), Ok(8));
+        assert_eq!(eval("A + B % 3 * 2"), Ok(8));
+        assert_eq!(eval("1 + 2 < 4 == 1"), Ok(1));
+        assert_eq!(eval("0 || 1 && 0"), Ok(0));
+    }
+
+    #[test]
+    fn unary_minus() {
+        assert_eq!(eval("-A"), Ok(-6));
+        assert_eq!(eval("--A"), Ok(6));
+        assert_eq!(eval("-A * B"), Ok(-24));
+        assert_eq!(eval("2 - -3"), Ok(5));
+        assert_eq!(eval("-7 / 2"), Ok(-3));
+        assert_eq!(eval("-7 % 2"), Ok(-1));
+    }
+
+    #[test]
+    fn comparisons_and_booleans() {
+        assert_eq!(eval("A > B"), Ok(1));
+        assert_eq!(eval("A <= B"), Ok(0));
+        assert_eq!(eval("A != B && B >= 4"), Ok(1));
+        assert_eq!(eval("!A"), Ok(0));
+        assert_eq!(eval("!Z"), Ok(1));
+        // The right hand side is not evaluated, so there is no division by zero
+        assert_eq!(eval("Z != 0 && A / Z > 1"), Ok(0));
+        assert_eq!(eval("Z == 0 || A / Z > 1"), Ok(1));
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(eval("min(A, B)"), Ok(4));
+        assert_eq!(eval("max(A, B + 3)"), Ok(7));
+        assert_eq!(eval("ceil_div(A, B)"), Ok(2));
+        assert_eq!(eval("round_up(A, B)"), Ok(8));
+        assert_eq!(eval("round_up(8, B)"), Ok(8));
+    }
+
+    #[test]
+    fn ceil_div_rounds_up_for_either_sign() {
+        let eval = |src: &str| Expr::parse(src).unwrap().eval(&Config::new());
+        assert_eq!(eval("ceil_div(7, 2)"), Ok(4));
+        assert_eq!(eval("ceil_div(7, -2)"), Ok(-3));
+        assert_eq!(eval("ceil_div(-7, 2)"), Ok(-3));
+        assert_eq!(eval("ceil_div(-7, -2)"), Ok(4));
+        assert_eq!(eval("ceil_div(-8, 2)"), Ok(-4));
+        assert_eq!(eval("ceil_div(0, -3)"), Ok(0));
+        assert_eq!(eval("round_up(-7, 2)"), Ok(-6));
+        assert_eq!(eval("round_up(7, -2)"),
+                   Err("Rounding up to a negative multiple in round_up(7, -2).".to_string()));
+    }
+
+    #[test]
+    fn parse_errors_have_positions() {
+        assert_eq!(Expr::parse("A + ) * 2"),
+                   Err("Unexpected ')' at position 4 in 'A + ) * 2'.".to_string()));
+        assert_eq!(Expr::parse("A + "),
+                   Err("Unexpected end of expression at position 4 in 'A + '.".to_string()));
+        assert_eq!(Expr::parse("(A + B"),
+                   Err("Unexpected end of expression at position 6 in '(A + B'.".to_string()));
+        assert_eq!(Expr::parse("min(A B)"),
+                   Err("Expected ',' but found 'B' at position 6 in 'min(A B)'.".to_string()));
+        assert_eq!(Expr::parse("A B"),
+                   Err("Unexpected 'B' at position 2 in 'A B'.".to_string()));
+        assert_eq!(Expr::parse("A # B"),
+                   Err("Unexpected character '#' at position 2 in 'A # B'.".to_string()));
+        assert_eq!(Expr::parse("foo(A, B)"),
+                   Err("Unknown function 'foo' at position 0 in 'foo(A, B)'.".to_string()));
+    }
+
+    #[test]
+    fn unknown_identifiers() {
+        assert!(Expr::parse_with_names("A * B", &["A", "B"]).is_ok());
+        assert_eq!(Expr::parse_with_names("A * C + D", &["A", "B"]),
+                   Err("Unknown parameters [\"C\", \"D\"] in '((A * C) + D)'.".to_string()));
+        assert_eq!(eval("C"), Err("Parameter C does not exist.".to_string()));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert_eq!(eval("A / Z"), Err("Division by zero in (A / Z).".to_string()));
+        assert_eq!(eval("A % Z"), Err("Modulo by zero in (A % Z).".to_string()));
+        assert!(eval("ceil_div(A, Z)").is_err());
+        assert!(eval("round_up(A, Z)").is_err());
+        let constraint = Expr::parse("A % Z == 0").unwrap().into_constraint();
+        assert!(!constraint.call(&config(&[("A", 6), ("Z", 0)])));
+        let formula = Expr::parse("A / Z").unwrap().into_formula();
+        assert!(formula.call(&config(&[("A", 6), ("Z", 0)])).is_err());
+    }
+
+    #[test]
+    fn overflow() {
+        let max = config(&[("M", i32::MAX)]);
+        let eval = |src: &str| Expr::parse(src).unwrap().eval(&max);
+        assert_eq!(eval("M * M * M"), Err("Overflow in ((M * M) * M).".to_string()));
+        assert!(eval("-9223372036854775807 - 2").is_err());
+        assert!(eval("(-9223372036854775807 - 1) / -1").is_err());
+        assert!(eval("-(-9223372036854775807 - 1)").is_err());
+        assert_eq!(eval("ceil_div(9223372036854775807, 2)"), Ok(1 << 62));
+        assert!(eval("ceil_div(-9223372036854775807 - 1, -1)").is_err());
+        assert!(eval("round_up(9223372036854775807, 2)").is_err());
+        let formula = Expr::parse("M + 1").unwrap().into_formula();
+        assert!(formula.call(&max).is_err());
+        let size = Expr::parse("M - 2 * M").unwrap().into_work_size();
+        assert!(size(0, &max).is_err());
+        let size = Expr::parse("M + 1").unwrap().into_work_size();
+        assert_eq!(size(0, &max), Ok(1 << 31));
+    }
+}
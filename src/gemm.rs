@@ -1,8 +1,8 @@
-use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
 
 use core::*;
+use data::Access;
 use ocl::SpatialDims;
 
 pub fn build_kernel_wrapper(file: &str, m: usize, n: usize, k: usize) -> KernelWrapper {
@@ -13,18 +13,26 @@ pub fn build_kernel_wrapper(file: &str, m: usize, n: usize, k: usize) -> KernelW
         .unwrap();
     KernelWrapper {
         scalar_inputs: vec![m as i32, n as i32, k as i32],
-        inputs_dims: vec![(m, k), (k, n), (m, n)],
-        src: src,
+        inputs_dims: vec![vec![m, k], vec![k, n], vec![m, n]],
+        inputs_padding: Vec::new(),
+        inputs_init: Vec::new(),
+        inputs_access: vec![Access::Input, Access::Input, Access::Output],
+        src,
         name: "gemm_fast".into(),
+        problem_size: vec![("M".into(), m), ("N".into(), n), ("K".into(), k)],
         ref_name: None,
         global_base: SpatialDims::Two(m, n),
         local_base: SpatialDims::Two(1, 1),
+        args: None,
+        images: Vec::new(),
+        flops: Some((2 * m * n * k) as u64),
+        bytes: Some((4 * (m * k + k * n + m * n)) as u64),
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct GemmBuilder {
-    parameters: HashMap<String, Vec<i32>>,
+    builder: ParameterSetBuilder,
 }
 
 impl Default for GemmBuilder {
@@ -45,86 +53,144 @@ impl Default for GemmBuilder {
             .sa(vec![true])
             .sb(vec![true])
             .precision(vec![32])
+            .use_vector_mad(vec![true])
+            .use_cl_mad(vec![false])
     }
 }
 
 
 impl GemmBuilder {
     pub fn new() -> Self {
-        GemmBuilder { parameters: HashMap::new() }
+        let mut builder = ParameterSetBuilder::new();
+        for &name in &["MWG",
+                       "NWG",
+                       "KWG",
+                       "MDIMC",
+                       "NDIMC",
+                       "SA",
+                       "SB",
+                       "MDIMA",
+                       "NDIMB",
+                       "KWI",
+                       "VWM",
+                       "VWN",
+                       "STRM",
+                       "STRN",
+                       "PRECISION",
+                       "USE_VECTOR_MAD",
+                       "USE_CL_MAD"] {
+            builder = builder.param::<i32>(name, vec![]);
+        }
+        let builder = builder
+            // Helper parameters, as defined in gemm.ocl
+            .derived("MWI", "MWG / MDIMC")
+            .derived("NWI", "NWG / NDIMC")
+            .derived("KDIMA", "(MDIMC * NDIMC) / MDIMA")
+            .derived("KDIMB", "(MDIMC * NDIMC) / NDIMB")
+            .derived("MWA", "MWG / MDIMA")
+            .derived("KWA", "KWG / KDIMA")
+            .derived("KWB", "KWG / KDIMB")
+            .derived("NWB", "NWG / NDIMB")
+            // MDIMA and NDIMB only matter when caching in local memory
+            .condition("MDIMA", "SA == 1")
+            .condition("NDIMB", "SB == 1")
+            .condition("USE_CL_MAD", "USE_VECTOR_MAD == 0")
+            // Requirement for unrolling the KWG loop
+            .constraint("KWG % KWI == 0")
+            // Required for integer MWI and NWI
+            .constraint("MWG % (MDIMC * VWM) == 0")
+            .constraint("NWG % (NDIMC * VWN) == 0")
+            // Required for integer MWIA and NWIB
+            .constraint("SA == 0 || MWG % (MDIMA * VWM) == 0")
+            .constraint("SB == 0 || NWG % (NDIMB * VWN) == 0")
+            // KWG has to be a multiple of KDIMA and KDIMB
+            .constraint("SA == 0 || KWG % KDIMA == 0")
+            .constraint("SB == 0 || KWG % KDIMB == 0")
+            .local_memory("(SA * KWG * MWG / VWM + SB * KWG * NWG / VWN) * (PRECISION / 8)")
+            .mul_global_size(vec![Some("MDIMC"), Some("NDIMC")])
+            .mul_local_size(vec![Some("MDIMC"), Some("NDIMC")])
+            .div_global_size(vec![Some("MWG"), Some("NWG")]);
+        GemmBuilder { builder }
     }
 
     pub fn mwg(mut self, values: Vec<i32>) -> Self {
-        self.parameters.insert("MWG".into(), values);
+        self.builder = self.builder.param("MWG", values);
         self
     }
 
     pub fn nwg(mut self, values: Vec<i32>) -> Self {
-        self.parameters.insert("NWG".into(), values);
+        self.builder = self.builder.param("NWG", values);
         self
     }
 
     pub fn kwg(mut self, values: Vec<i32>) -> Self {
-        self.parameters.insert("KWG".into(), values);
+        self.builder = self.builder.param("KWG", values);
         self
     }
 
     pub fn mdimc(mut self, values: Vec<i32>) -> Self {
-        self.parameters.insert("MDIMC".into(), values);
+        self.builder = self.builder.param("MDIMC", values);
         self
     }
 
     pub fn ndimc(mut self, values: Vec<i32>) -> Self {
-        self.parameters.insert("NDIMC".into(), values);
+        self.builder = self.builder.param("NDIMC", values);
         self
     }
 
     pub fn mdima(mut self, values: Vec<i32>) -> Self {
-        self.parameters.insert("MDIMA".into(), values);
+        self.builder = self.builder.param("MDIMA", values);
         self
     }
 
     pub fn ndimb(mut self, values: Vec<i32>) -> Self {
-        self.parameters.insert("NDIMB".into(), values);
+        self.builder = self.builder.param("NDIMB", values);
         self
     }
 
     pub fn kwi(mut self, values: Vec<i32>) -> Self {
-        self.parameters.insert("KWI".into(), values);
+        self.builder = self.builder.param("KWI", values);
         self
     }
 
     pub fn vwm(mut self, values: Vec<i32>) -> Self {
-        self.parameters.insert("VWM".into(), values);
+        self.builder = self.builder.param("VWM", values);
         self
     }
 
     pub fn vwn(mut self, values: Vec<i32>) -> Self {
-        self.parameters.insert("VWN".into(), values);
+        self.builder = self.builder.param("VWN", values);
         self
     }
 
     pub fn strm(mut self, values: Vec<bool>) -> Self {
-        let values = values.into_iter().map(|x| x as i32).collect();
-        self.parameters.insert("STRM".into(), values);
+        self.builder = self.builder.param("STRM", values);
         self
     }
 
     pub fn strn(mut self, values: Vec<bool>) -> Self {
-        let values = values.into_iter().map(|x| x as i32).collect();
-        self.parameters.insert("STRN".into(), values);
+        self.builder = self.builder.param("STRN", values);
         self
     }
 
     pub fn sa(mut self, values: Vec<bool>) -> Self {
-        let values = values.into_iter().map(|x| x as i32).collect();
-        self.parameters.insert("SA".into(), values);
+        self.builder = self.builder.param("SA", values);
         self
     }
 
     pub fn sb(mut self, values: Vec<bool>) -> Self {
-        let values = values.into_iter().map(|x| x as i32).collect();
-        self.parameters.insert("SB".into(), values);
+        self.builder = self.builder.param("SB", values);
+        self
+    }
+
+    pub fn use_vector_mad(mut self, values: Vec<bool>) -> Self {
+        self.builder = self.builder.param("USE_VECTOR_MAD", values);
+        self
+    }
+
+    /// Only used when `USE_VECTOR_MAD` is false.
+    pub fn use_cl_mad(mut self, values: Vec<bool>) -> Self {
+        self.builder = self.builder.param("USE_CL_MAD", values);
         self
     }
 
@@ -134,111 +200,87 @@ impl GemmBuilder {
                 panic!("Precision can be only 32 or 64.")
             }
         }
-        self.parameters.insert("PRECISION".into(), values);
-        self
-    }
-
-    pub fn build<'a>(self) -> Result<ParameterSet<'a>, String> {
-        let ordered = vec!["MWG",
-                           "NWG",
-                           "KWG",
-                           "MDIMC",
-                           "NDIMC",
-                           "MDIMA",
-                           "NDIMB",
-                           "KWI",
-                           "VWM",
-                           "VWN",
-                           "STRM",
-                           "STRN",
-                           "SA",
-                           "SB",
-                           "PRECISION"];
-        for &name in &ordered {
-            if self.parameters.get(name).is_none() {
-                return Err(format!("The GEMM parameter set for '{}' has not been set.", name));
+        self.builder = self.builder.param("PRECISION", values);
+        self
+    }
+
+    pub fn build(self) -> Result<ParameterSet, String> {
+        self.builder.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use spec;
+    use values;
+
+    use super::*;
+
+    #[test]
+    fn builder_matches_the_spec_template() {
+        let params = GemmBuilder::new()
+            .mwg(values::powers_of_two(32, 256))
+            .nwg(values::powers_of_two(32, 256))
+            .kwg(values::divisors(2048, 8, 32))
+            .mdimc(vec![32, 16, 8])
+            .ndimc(vec![32, 16, 8])
+            .sa(vec![true])
+            .sb(vec![true])
+            .mdima(vec![32, 16, 8])
+            .ndimb(vec![32, 16, 8])
+            .kwi(values::powers_of_two(2, 16))
+            .vwm(vec![1])
+            .vwn(vec![1])
+            .strm(vec![true])
+            .strn(vec![true])
+            .precision(vec![32])
+            .use_vector_mad(vec![true])
+            .use_cl_mad(vec![false])
+            .build()
+            .unwrap();
+        let path = format!("{}/templates/gemm.toml", env!("CARGO_MANIFEST_DIR"));
+        let (_, spec) = spec::load(&path).unwrap();
+        assert_eq!(params.all_names(), spec.all_names());
+
+        // Every configuration has the same derived values, constraints and local memory
+        let mut indexes = vec![0; params.parameters.len()];
+        let mut valid = 0;
+        'configurations: loop {
+            assert_eq!(params.is_canonical(&indexes), spec.is_canonical(&indexes));
+            let config = params.config(&indexes).unwrap();
+            let spec_config = spec.config(&indexes).unwrap();
+            for &name in &params.derived_names() {
+                assert_eq!(config[name], spec_config[name]);
             }
+            let ok: Vec<bool> = params.constraints.iter().map(|c| c.call(&config)).collect();
+            let spec_ok: Vec<bool> =
+                spec.constraints.iter().map(|c| c.call(&spec_config)).collect();
+            assert_eq!(ok, spec_ok);
+            if ok.iter().all(|&ok| ok) {
+                valid += 1;
+                let local_memory = params.local_memory_needed.as_ref().unwrap();
+                assert_eq!(local_memory.call(&config),
+                           spec.local_memory_needed.as_ref().unwrap().call(&spec_config));
+            }
+            for i in (0..indexes.len()).rev() {
+                indexes[i] += 1;
+                if indexes[i] < params.parameters[i].1.len() {
+                    continue 'configurations;
+                }
+                indexes[i] = 0;
+            }
+            break;
         }
-        let parameters = ordered
-            .iter()
-            .map(move |&x| {
-                     let s: String = x.into();
-                     let v = self.parameters[&s].clone();
-                     (s, v)
-                 })
-            .collect();
-        let mut constraints: Vec<FnWrap<'static, bool>> = Vec::new();
-        fn multiple_of_x(v: &[i32]) -> bool {
-            v[0] % v[1] == 0
-        }
-        fn multiple_of_x_mul_y(v: &[i32]) -> bool {
-            v[0] % (v[1] * v[2]) == 0
-        }
-        fn multiple_of_x_mul_y_div_z(v: &[i32]) -> bool {
-            v[0] % ((v[1] * v[2]) / v[3]) == 0
-        }
+        assert!(valid > 0);
+    }
 
-        // Sets constraints: Requirement for unrolling the KWG loop
-        constraints.push(FnWrap {
-                             func: multiple_of_x,
-                             args: vec!["KWG", "KWI"],
-                         });
-
-        // Sets constraints: Required for integer MWI and NWI
-        constraints.push(FnWrap {
-                             func: multiple_of_x_mul_y,
-                             args: vec!["MWG", "MDIMC", "VWM"],
-                         });
-        constraints.push(FnWrap {
-                             func: multiple_of_x_mul_y,
-                             args: vec!["NWG", "NDIMC", "VWN"],
-                         });
-
-        // Sets constraints: Required for integer MWIA and NWIB
-        constraints.push(FnWrap {
-                             func: multiple_of_x_mul_y,
-                             args: vec!["MWG", "MDIMA", "VWM"],
-                         });
-        constraints.push(FnWrap {
-                             func: multiple_of_x_mul_y,
-                             args: vec!["NWG", "NDIMB", "VWN"],
-                         });
-
-        // Sets constraints: KWG has to be a multiple of MDIMC * NDIMC / MDIMA
-        constraints.push(FnWrap {
-                             func: multiple_of_x_mul_y_div_z,
-                             args: vec!["KWG", "MDIMC", "NDIMC", "MDIMA"],
-                         });
-        constraints.push(FnWrap {
-                             func: multiple_of_x_mul_y_div_z,
-                             args: vec!["KWG", "MDIMC", "NDIMC", "NDIMB"],
-                         });
-
-        // SA * KWG * MWG / VWM + SB * KWG * NWG / VWN
-        // Arguments are ordered as MWG, NWG, KWG, VWM, VWN, SA, SB, PRECISION
-        fn calc_local_memory(v: &[i32]) -> i32 {
-            let mwg = v[0];
-            let nwg = v[1];
-            let kwg = v[2];
-            let vwm = v[3];
-            let vwn = v[4];
-            let sa = v[5];
-            let sb = v[6];
-            let p = v[7];
-            (sa * mwg / vwm + sb * nwg / vwn) * kwg * (p / 8)
-        }
-        let local_memory_needed = FnWrap {
-            func: calc_local_memory,
-            args: vec!["MWG", "NWG", "KWG", "VWM", "VWN", "SA", "SB", "PRECISION"],
-        };
-
-        Ok(ParameterSet {
-               parameters: parameters,
-               constraints: constraints,
-               local_memory_needed: Some(local_memory_needed),
-               mul_global_size: Some(vec![Some("MDIMC".into()), Some("NDIMC".into())]),
-               mul_local_size: Some(vec![Some("MDIMC".into()), Some("NDIMC".into())]),
-               div_global_size: Some(vec![Some("MWG".into()), Some("NWG".into())]),
-           })
+    #[test]
+    fn setters_keep_the_parameter_order() {
+        let params = GemmBuilder::default().kwi(vec![2, 8]).mwg(vec![32, 64]).build().unwrap();
+        assert_eq!(&params.names()[..3], &["MWG", "NWG", "KWG"]);
+        assert_eq!(params.parameters[0].1, vec![Value::Int(32), Value::Int(64)]);
+        assert_eq!(params.parameters[9].1, vec![Value::Int(2), Value::Int(8)]);
+        assert_eq!(params.names().len(), 17);
+        assert_eq!(params.derived_names().len(), 8);
     }
 }
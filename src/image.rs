@@ -0,0 +1,319 @@
+use std::str::FromStr;
+
+use ocl::{Context, Image, Kernel, Queue, Sampler, SpatialDims};
+use ocl::enums::{AddressingMode, FilterMode, ImageChannelDataType, ImageChannelOrder,
+                 MemObjectType};
+use ocl::flags::MemFlags;
+
+use core::{Config, Value};
+use data::{Access, Init};
+
+/// The channel order of an image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelOrder {
+    R,
+    RG,
+    RGBA,
+}
+
+/// The channel data type of an image. Generated data is converted from `f32`, with `Unorm8`
+/// clamped to `[0, 1]` and the integer types truncated, so they need an explicit `init`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelType {
+    Float,
+    Int32,
+    UInt32,
+    Unorm8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageFormat {
+    pub order: ChannelOrder,
+    pub data_type: ChannelType,
+}
+
+impl ImageFormat {
+    /// Whether the channels are integers, which the default `init` would fill with zeros.
+    pub fn is_integer(&self) -> bool {
+        match self.data_type {
+            ChannelType::Int32 | ChannelType::UInt32 => true,
+            ChannelType::Float | ChannelType::Unorm8 => false,
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        match self.order {
+            ChannelOrder::R => 1,
+            ChannelOrder::RG => 2,
+            ChannelOrder::RGBA => 4,
+        }
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = String;
+
+    /// Parses `<order>_<type>` with the order `R`, `RG` or `RGBA` and the type `FLOAT`,
+    /// `INT32`, `UINT32` or `UNORM8`, e.g. `RGBA_FLOAT`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let upper = s.to_uppercase();
+        let mut parts = upper.splitn(2, '_');
+        let order = match parts.next() {
+            Some("R") => ChannelOrder::R,
+            Some("RG") => ChannelOrder::RG,
+            Some("RGBA") => ChannelOrder::RGBA,
+            _ => return Err(format!("Unknown channel order in image format '{}'.", s)),
+        };
+        let data_type = match parts.next() {
+            Some("FLOAT") => ChannelType::Float,
+            Some("INT32") => ChannelType::Int32,
+            Some("UINT32") => ChannelType::UInt32,
+            Some("UNORM8") => ChannelType::Unorm8,
+            _ => return Err(format!("Unknown channel type in image format '{}'.", s)),
+        };
+        Ok(ImageFormat { order, data_type })
+    }
+}
+
+/// A 2-D or 3-D image argument, created and filled by the tuner like a buffer.
+#[derive(Clone, Debug)]
+pub struct ImageArg {
+    /// Width, height and optionally depth, in pixels.
+    pub dims: Vec<usize>,
+    pub format: ImageFormat,
+    /// A string parameter whose value, e.g. `"RGBA_FLOAT"`, selects the format for each
+    /// configuration instead of `format`, to compare formats in one tuning run.
+    pub format_param: Option<String>,
+    /// The initial contents, required for integer formats. Float formats default to
+    /// `Init::default()`.
+    pub init: Option<Init>,
+    /// Whether the kernel reads, writes or does both. Output images are not initialised.
+    pub access: Access,
+}
+
+impl ImageArg {
+    pub fn new(dims: Vec<usize>, format: ImageFormat) -> Self {
+        ImageArg {
+            dims,
+            format,
+            format_param: None,
+            init: None,
+            access: Access::Input,
+        }
+    }
+
+    /// The format of the image for a configuration.
+    pub fn format(&self, config: &Config) -> Result<ImageFormat, String> {
+        match self.format_param {
+            Some(ref name) => {
+                match config.get(name) {
+                    Some(Value::Str(format)) => ImageFormat::from_str(format),
+                    _ => Err(format!("Parameter {} must be a string image format.", name)),
+                }
+            }
+            None => Ok(self.format),
+        }
+    }
+
+    /// The initial contents of the image in the given format from `init`, the seeded
+    /// initialiser of the image, or nothing for an output image.
+    pub fn generate(&self, format: ImageFormat, init: &Init) -> Result<Vec<f32>, String> {
+        if self.access == Access::Output {
+            return Ok(Vec::new());
+        }
+        if self.init.is_none() && format.is_integer() {
+            return Err("Images with an integer format need an explicit init.".into());
+        }
+        init.generate(self.dims.iter().product::<usize>() * format.channels())
+    }
+
+    /// Creates the image with the given format, filled with `data` unless it is empty.
+    pub fn create(&self, queue: &Queue, format: ImageFormat, data: &[f32])
+                  -> Result<ImageData, String> {
+        let dims = match self.dims.len() {
+            2 => SpatialDims::Two(self.dims[0], self.dims[1]),
+            3 => SpatialDims::Three(self.dims[0], self.dims[1], self.dims[2]),
+            _ => return Err("Images must have 2 or 3 dimensions.".into()),
+        };
+        let image_type = if self.dims.len() == 2 {
+            MemObjectType::Image2d
+        } else {
+            MemObjectType::Image3d
+        };
+        let order = match format.order {
+            ChannelOrder::R => ImageChannelOrder::R,
+            ChannelOrder::RG => ImageChannelOrder::Rg,
+            ChannelOrder::RGBA => ImageChannelOrder::Rgba,
+        };
+        let flags = match self.access {
+            Access::Input => MemFlags::new().read_only().copy_host_ptr(),
+            Access::Output => MemFlags::new().write_only(),
+            Access::InOut => MemFlags::new().read_write().copy_host_ptr(),
+        };
+        macro_rules! image {
+            ($t:ty, $data_type:expr, $variant:path, $convert:expr) => {{
+                let data: Vec<$t> = convert(data, $convert);
+                let builder = Image::<$t>::builder()
+                    .channel_order(order)
+                    .channel_data_type($data_type)
+                    .image_type(image_type)
+                    .dims(dims)
+                    .flags(flags);
+                let builder = if data.is_empty() {
+                    builder
+                } else {
+                    builder.host_data(&data)
+                };
+                builder
+                    .build(queue)
+                    .map($variant)
+                    .map_err(|e| e.to_string())
+            }}
+        }
+        match format.data_type {
+            ChannelType::Float => {
+                image!(f32, ImageChannelDataType::Float, ImageData::Float, |&x| x)
+            }
+            ChannelType::Int32 => {
+                image!(i32, ImageChannelDataType::SignedInt32, ImageData::Int32, int32)
+            }
+            ChannelType::UInt32 => {
+                image!(u32, ImageChannelDataType::UnsignedInt32, ImageData::UInt32, uint32)
+            }
+            ChannelType::Unorm8 => {
+                image!(u8, ImageChannelDataType::UnormInt8, ImageData::Unorm8, unorm8)
+            }
+        }
+    }
+}
+
+/// An image created by the tuner, with the element type of its channel data type.
+pub enum ImageData {
+    Float(Image<f32>),
+    Int32(Image<i32>),
+    UInt32(Image<u32>),
+    Unorm8(Image<u8>),
+}
+
+impl ImageData {
+    pub fn set(&self, kernel: Kernel) -> Kernel {
+        match *self {
+            ImageData::Float(ref image) => kernel.arg_img(image),
+            ImageData::Int32(ref image) => kernel.arg_img(image),
+            ImageData::UInt32(ref image) => kernel.arg_img(image),
+            ImageData::Unorm8(ref image) => kernel.arg_img(image),
+        }
+    }
+
+    /// Overwrites the contents of the image, converting them like `ImageArg::create`.
+    pub fn write(&self, data: &[f32]) -> Result<(), String> {
+        match *self {
+                ImageData::Float(ref image) => image.write(data).enq(),
+                ImageData::Int32(ref image) => image.write(&convert(data, int32)).enq(),
+                ImageData::UInt32(ref image) => image.write(&convert(data, uint32)).enq(),
+                ImageData::Unorm8(ref image) => image.write(&convert(data, unorm8)).enq(),
+            }
+            .map_err(|e| e.to_string())
+    }
+}
+
+fn convert<T>(data: &[f32], f: fn(&f32) -> T) -> Vec<T> {
+    data.iter().map(f).collect()
+}
+
+fn int32(x: &f32) -> i32 {
+    *x as i32
+}
+
+fn uint32(x: &f32) -> u32 {
+    *x as u32
+}
+
+fn unorm8(x: &f32) -> u8 {
+    (x.clamp(0.0, 1.0) * 255.0) as u8
+}
+
+/// A sampler argument.
+#[derive(Clone, Copy, Debug)]
+pub struct SamplerArg {
+    pub normalized_coords: bool,
+    pub addressing_mode: AddressingMode,
+    pub filter_mode: FilterMode,
+}
+
+impl Default for SamplerArg {
+    fn default() -> Self {
+        SamplerArg {
+            normalized_coords: false,
+            addressing_mode: AddressingMode::ClampToEdge,
+            filter_mode: FilterMode::Nearest,
+        }
+    }
+}
+
+impl FromStr for SamplerArg {
+    type Err = String;
+
+    /// Parses comma separated options over the default sampler: `nearest` or `linear`,
+    /// `none`, `clamp`, `clamp_to_edge`, `repeat` or `mirrored_repeat`, and `normalized`,
+    /// e.g. `linear, repeat, normalized`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut sampler = SamplerArg::default();
+        for option in s.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+            match option {
+                "nearest" => sampler.filter_mode = FilterMode::Nearest,
+                "linear" => sampler.filter_mode = FilterMode::Linear,
+                "none" => sampler.addressing_mode = AddressingMode::None,
+                "clamp" => sampler.addressing_mode = AddressingMode::Clamp,
+                "clamp_to_edge" => sampler.addressing_mode = AddressingMode::ClampToEdge,
+                "repeat" => sampler.addressing_mode = AddressingMode::Repeat,
+                "mirrored_repeat" => sampler.addressing_mode = AddressingMode::MirroredRepeat,
+                "normalized" => sampler.normalized_coords = true,
+                _ => return Err(format!("Unknown sampler option '{}'.", option)),
+            }
+        }
+        Ok(sampler)
+    }
+}
+
+impl SamplerArg {
+    pub fn create(&self, context: &Context) -> Result<Sampler, String> {
+        Sampler::new(context,
+                     self.normalized_coords,
+                     self.addressing_mode,
+                     self.filter_mode)
+                .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_contents_depend_on_access() {
+        let format = ImageFormat::from_str("RG_FLOAT").unwrap();
+        let init = Init::uniform(0.0, 1.0).seed(1);
+        let mut image = ImageArg::new(vec![4, 3], format);
+        let data = image.generate(format, &init).unwrap();
+        assert_eq!(data.len(), 24);
+
+        // In/out images are reset to the same contents before every launch
+        image.access = Access::InOut;
+        assert_eq!(image.generate(format, &init).unwrap(), data);
+        image.access = Access::Output;
+        assert!(image.generate(format, &init).unwrap().is_empty());
+    }
+
+    #[test]
+    fn integer_formats_need_an_init() {
+        let format = ImageFormat::from_str("R_INT32").unwrap();
+        let mut image = ImageArg::new(vec![4, 4], format);
+        assert!(image.generate(format, &Init::default()).is_err());
+        image.access = Access::Output;
+        assert!(image.generate(format, &Init::default()).is_ok());
+        image.access = Access::Input;
+        image.init = Some(Init::uniform(0.0, 256.0));
+        assert_eq!(image.generate(format, &Init::uniform(0.0, 256.0).seed(2)).unwrap().len(), 16);
+    }
+}
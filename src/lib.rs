@@ -1,10 +1,25 @@
 extern crate rand;
 extern crate ocl;
 extern crate futures;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate toml;
 
 
+pub mod args;
 pub mod core;
+pub mod data;
+pub mod device;
+pub mod expr;
 pub mod gemm;
+pub mod image;
+pub mod multi;
+pub mod results;
 pub mod simple;
+pub mod spec;
+pub mod sweep;
+pub mod values;
 
 pub use core::*;
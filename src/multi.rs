@@ -0,0 +1,88 @@
+use std::thread;
+use std::path::Path;
+
+use core::{Tuner, KernelWrapper, ParameterSet};
+use device::{PlatformSelector, DeviceSelector};
+use results::TuningResults;
+
+/// Tunes the same kernel on several devices in parallel, with one host thread per device.
+#[derive(Clone, Debug)]
+pub struct MultiTuner {
+    tuners: Vec<Tuner>,
+}
+
+impl MultiTuner {
+    pub fn new(tuners: Vec<Tuner>) -> Self {
+        MultiTuner { tuners }
+    }
+
+    /// Creates a tuner for every device matching the selector on the platforms matching the
+    /// platform selector.
+    pub fn select(platform: &PlatformSelector, device: &DeviceSelector) -> Result<Self, String> {
+        let devices = platform.select_devices(device)?;
+        if devices.is_empty() {
+            return Err(format!("No device on a platform matching {:?} matches {:?}.",
+                               platform,
+                               device));
+        }
+        Ok(MultiTuner::new(devices
+                               .into_iter()
+                               .map(|(p, d)| Tuner::from_device(p, d))
+                               .collect()))
+    }
+
+    pub fn tuners(&self) -> &[Tuner] {
+        &self.tuners
+    }
+
+    /// Runs `Tuner::tune` on every device and returns the results in the order of the tuners,
+    /// or the first error.
+    /// When a log file is given, each device writes to its own file with the device index
+    /// appended to the file stem, e.g. `gemm_0.csv`, `gemm_1.csv`.
+    pub fn tune(&self, wrapper: KernelWrapper, params: ParameterSet, runs: usize,
+                log_file: Option<&str>)
+                -> Result<Vec<TuningResults>, String> {
+        let handles: Vec<_> = self.tuners
+            .iter()
+            .enumerate()
+            .map(|(i, tuner)| {
+                let tuner = tuner.clone().verbose(false);
+                let wrapper = wrapper.clone();
+                let params = params.clone();
+                let log_file = log_file.map(|f| indexed_log_file(f, i));
+                thread::spawn(move || {
+                    let log_file = log_file.as_ref().map(|f| &f[..]);
+                    tuner.tune(wrapper, params, runs, log_file)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().map_err(|_| "Tuning thread panicked.".to_string())?)
+            .collect()
+    }
+}
+
+/// The log file name with an index appended to its stem, e.g. `gemm_1.csv` for `gemm.csv`.
+pub fn indexed_log_file(file: &str, index: usize) -> String {
+    let path = Path::new(file);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or(file);
+    let name = match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => format!("{}_{}.{}", stem, index, ext),
+        None => format!("{}_{}", stem, index),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_files_are_indexed_per_device() {
+        assert_eq!(indexed_log_file("gemm.csv", 0), "gemm_0.csv");
+        assert_eq!(indexed_log_file("logs/gemm.jsonl", 3), "logs/gemm_3.jsonl");
+        assert_eq!(indexed_log_file("gemm", 1), "gemm_1");
+        assert_eq!(indexed_log_file("logs/run.v2.csv", 2), "logs/run.v2_2.csv");
+    }
+}
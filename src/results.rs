@@ -0,0 +1,565 @@
+use std::fs::File;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::{self, Map};
+
+use core::Value;
+use device::{DeviceFingerprint, KernelLimits};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The time of a single valid configuration.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TuningResult {
+    pub parameters: Vec<(String, Value)>,
+    #[serde(default)]
+    pub derived: Vec<(String, Value)>,
+    pub time: Duration,
+    pub kernel_limits: KernelLimits,
+}
+
+/// All results from tuning one kernel on one device, together with the device fingerprint,
+/// kernel name, problem size and crate version they were obtained with.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TuningResults {
+    pub fingerprint: DeviceFingerprint,
+    pub kernel: String,
+    pub problem_size: Vec<(String, usize)>,
+    pub version: String,
+    /// The initialiser of each input buffer, including the seed of random ones.
+    #[serde(default)]
+    pub inputs: Vec<String>,
+    /// The floating point operations and bytes moved by one launch, see `KernelWrapper`.
+    #[serde(default)]
+    pub flops: Option<u64>,
+    #[serde(default)]
+    pub bytes: Option<u64>,
+    /// The theoretical peak of the device in GFLOPS and GB/s, if known.
+    #[serde(default)]
+    pub peak_gflops: Option<f64>,
+    #[serde(default)]
+    pub peak_bandwidth: Option<f64>,
+    pub results: Vec<TuningResult>,
+}
+
+impl TuningResults {
+    pub fn new(fingerprint: DeviceFingerprint, kernel: String,
+               problem_size: Vec<(String, usize)>)
+               -> Self {
+        TuningResults {
+            fingerprint,
+            kernel,
+            problem_size,
+            version: VERSION.into(),
+            inputs: Vec::new(),
+            flops: None,
+            bytes: None,
+            peak_gflops: None,
+            peak_bandwidth: None,
+            results: Vec::new(),
+        }
+    }
+
+    pub fn best(&self) -> Option<&TuningResult> {
+        self.results.iter().min_by_key(|r| r.time)
+    }
+
+    /// The names of the throughput metrics known for the kernel, see `metrics`.
+    pub fn metric_names(&self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        if self.flops.is_some() {
+            names.push("GFLOPS");
+            if self.peak_gflops.is_some() {
+                names.push("%peak");
+            }
+        }
+        if self.bytes.is_some() {
+            names.push("GB/s");
+            if self.peak_bandwidth.is_some() {
+                names.push("%bw");
+            }
+        }
+        names
+    }
+
+    /// The throughput of a launch taking `time`: GFLOPS and effective GB/s, each followed by
+    /// its percentage of the peak of the device when known.
+    pub fn metrics(&self, time: Duration) -> Vec<f64> {
+        let mut metrics = Vec::new();
+        let rate = |amount: u64| amount as f64 / seconds(time) / 1e9;
+        if let Some(flops) = self.flops {
+            metrics.push(rate(flops));
+            if let Some(peak) = self.peak_gflops {
+                metrics.push(100.0 * rate(flops) / peak);
+            }
+        }
+        if let Some(bytes) = self.bytes {
+            metrics.push(rate(bytes));
+            if let Some(peak) = self.peak_bandwidth {
+                metrics.push(100.0 * rate(bytes) / peak);
+            }
+        }
+        metrics
+    }
+
+    pub fn sort_by_time(&mut self) {
+        self.results.sort_by_key(|r| r.time);
+    }
+
+    /// Sorts by the value of a parameter, and by time for equal values.
+    pub fn sort_by_parameter(&mut self, name: &str) -> Result<(), String> {
+        if self.results.iter().any(|r| r.get(name).is_none()) {
+            return Err(format!("Parameter {} does not exist.", name));
+        }
+        self.results.sort_by_key(|r| (r.get(name).cloned(), r.time));
+        Ok(())
+    }
+
+    /// Prints the metadata and a table of the first `limit` results.
+    pub fn print(&self, limit: Option<usize>) {
+        for (k, v) in self.metadata() {
+            println!("{}: {}", k, v);
+        }
+        let results = &self.results[..limit.unwrap_or(self.results.len()).min(self.results.len())];
+        if let Some(first) = results.first() {
+            let names: Vec<&str> = first
+                .parameters
+                .iter()
+                .chain(first.derived.iter())
+                .map(|(k, _)| k.as_str())
+                .collect();
+            print_header(&names, &self.metric_names());
+        }
+        for result in results {
+            let values: Vec<Value> = result
+                .parameters
+                .iter()
+                .chain(result.derived.iter())
+                .map(|(_, v)| v.clone())
+                .collect();
+            print_row(&values, result.time, &self.metrics(result.time));
+        }
+    }
+
+    /// A database entry for the best configuration, in the form
+    /// `{ "vendor", "device", "kernel", { {"NAME", value}, ... } },`.
+    pub fn database_entry(&self) -> Option<String> {
+        self.best().map(|best| {
+            let values: Vec<String> = best.parameters
+                .iter()
+                .map(|(k, v)| format!("{{\"{}\", {}}}", k, v.define()))
+                .collect();
+            format!("{{ \"{}\", \"{}\", \"{}\", {{ {} }} }},",
+                    self.fingerprint.vendor,
+                    self.fingerprint.device,
+                    self.kernel,
+                    values.join(", "))
+        })
+    }
+
+    /// Everything except the results themselves as ordered `(key, value)` pairs.
+    pub fn metadata(&self) -> Vec<(String, String)> {
+        let mut metadata = vec![("k_tune_version".to_string(), self.version.clone()),
+                                ("kernel".to_string(), self.kernel.clone())];
+        for &(ref k, v) in &self.problem_size {
+            metadata.push((k.clone(), v.to_string()));
+        }
+        for (i, init) in self.inputs.iter().enumerate() {
+            metadata.push((format!("input_{}", i), init.clone()));
+        }
+        if let Some(flops) = self.flops {
+            metadata.push(("flops".into(), flops.to_string()));
+        }
+        if let Some(bytes) = self.bytes {
+            metadata.push(("bytes".into(), bytes.to_string()));
+        }
+        if let Some(peak) = self.peak_gflops {
+            metadata.push(("peak_gflops".into(), format!("{:.1}", peak)));
+        }
+        if let Some(peak) = self.peak_bandwidth {
+            metadata.push(("peak_bandwidth".into(), format!("{:.1}", peak)));
+        }
+        for (k, v) in self.fingerprint.fields() {
+            metadata.push((k.into(), v));
+        }
+        metadata
+    }
+
+    /// Writes the metadata as `# key: value` comment lines.
+    pub fn write_metadata(&self, f: &mut dyn Write) -> ::std::io::Result<()> {
+        for (k, v) in self.metadata() {
+            writeln!(f, "# {}: {}", k, v)?;
+        }
+        Ok(())
+    }
+}
+
+impl TuningResult {
+    /// The value of a tuned or derived parameter.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.parameters
+            .iter()
+            .chain(self.derived.iter())
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v)
+    }
+
+    /// The configuration as `#define` lines, for compiling the kernel outside of the tuner.
+    pub fn defines(&self) -> String {
+        self.parameters
+            .iter()
+            .chain(self.derived.iter())
+            .map(|(k, v)| format!("#define {} {}\n", k, v.define()))
+            .collect()
+    }
+}
+
+/// The format of the log file written while tuning.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// The metadata as `#` comment lines, then a header with the full parameter and metric
+    /// names and one row per timed configuration with its mean time in seconds, e.g. for
+    /// `pandas.read_csv(file, comment="#")`.
+    #[default]
+    Csv,
+    /// One JSON object per configuration, including skipped and failed ones, with its status,
+    /// the statistics of its runs, its work sizes and the device.
+    JsonLines,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(LogFormat::Csv),
+            "jsonl" => Ok(LogFormat::JsonLines),
+            _ => Err(format!("Unknown log format '{}', expected 'csv' or 'jsonl'.", s)),
+        }
+    }
+}
+
+/// What happened to a configuration.
+#[derive(Clone, Debug)]
+pub enum Status {
+    /// Timed over all runs, with the time of each run and their mean.
+    Ok(Vec<Duration>, Duration, KernelLimits),
+    /// Not run because it violates a constraint or a device limit.
+    Skipped(String),
+    /// Failed to build or run.
+    Failed(String),
+}
+
+/// Statistics in seconds over the runs of a configuration.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct TimeStats {
+    pub runs: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+    pub std: f64,
+}
+
+impl TimeStats {
+    /// The statistics of at least one time.
+    pub fn new(times: &[Duration]) -> Self {
+        assert!(!times.is_empty(), "No times to compute statistics of.");
+        let mut seconds: Vec<f64> = times.iter().map(|&t| seconds(t)).collect();
+        seconds.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let n = seconds.len() as f64;
+        let mean = seconds.iter().sum::<f64>() / n;
+        let median = if seconds.len().is_multiple_of(2) {
+            (seconds[seconds.len() / 2 - 1] + seconds[seconds.len() / 2]) / 2.0
+        } else {
+            seconds[seconds.len() / 2]
+        };
+        TimeStats {
+            runs: seconds.len(),
+            min: seconds[0],
+            max: seconds[seconds.len() - 1],
+            mean,
+            median,
+            std: (seconds.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / n).sqrt(),
+        }
+    }
+}
+
+/// The log written while tuning, see `LogFormat`, to a file or any other writer.
+pub struct Log<W: Write = File> {
+    format: LogFormat,
+    file: W,
+    names: Vec<String>,
+}
+
+impl Log {
+    /// Creates the log file, see `new`.
+    pub fn create(file: &str, format: LogFormat, results: &TuningResults, names: &[&str])
+                  -> Result<Self, String> {
+        let f = File::create(file).map_err(|e| format!("Could not create '{}': {}", file, e))?;
+        Log::new(f, format, results, names)
+    }
+}
+
+impl<W: Write> Log<W> {
+    /// Starts a log, writing the metadata and header of CSV files. `names` are the tuned and
+    /// derived parameters, in the order of the values given to `write`.
+    pub fn new(file: W, format: LogFormat, results: &TuningResults, names: &[&str])
+               -> Result<Self, String> {
+        let mut log = Log {
+            format,
+            file,
+            names: names.iter().map(|&k| k.into()).collect(),
+        };
+        if format == LogFormat::Csv {
+            log.write_csv_header(results).map_err(|e| e.to_string())?;
+        }
+        Ok(log)
+    }
+
+    fn write_csv_header(&mut self, results: &TuningResults) -> ::std::io::Result<()> {
+        results.write_metadata(&mut self.file)?;
+        let mut header: Vec<String> = self.names.iter().map(|k| csv_field(k)).collect();
+        header.push("time".into());
+        header.extend(results.metric_names().iter().map(|&k| csv_field(k)));
+        writeln!(self.file, "{}", header.join(","))
+    }
+
+    /// Logs a configuration. CSV files only get timed configurations.
+    pub fn write(&mut self, results: &TuningResults, values: &[Value], status: &Status,
+                 work_sizes: Option<(Vec<usize>, Vec<usize>)>)
+                 -> ::std::io::Result<()> {
+        match self.format {
+            LogFormat::Csv => {
+                let time = match *status {
+                    Status::Ok(_, time, _) => time,
+                    _ => return Ok(()),
+                };
+                let mut row: Vec<String> = values.iter().map(|v| csv_field(&v.define())).collect();
+                row.push(format!("{:.9}", seconds(time)));
+                row.extend(results.metrics(time).iter().map(|x| x.to_string()));
+                writeln!(self.file, "{}", row.join(","))
+            }
+            LogFormat::JsonLines => {
+                let line = self.json_line(results, values, status, work_sizes);
+                writeln!(self.file, "{}", line)
+            }
+        }
+    }
+
+    fn json_line(&self, results: &TuningResults, values: &[Value], status: &Status,
+                 work_sizes: Option<(Vec<usize>, Vec<usize>)>)
+                 -> serde_json::Value {
+        fn json<T: Serialize + ?Sized>(x: &T) -> serde_json::Value {
+            serde_json::to_value(x).unwrap()
+        }
+        let mut line = Map::new();
+        line.insert("kernel".into(), json(&results.kernel));
+        let size: Map<String, serde_json::Value> = results
+            .problem_size
+            .iter()
+            .map(|&(ref k, v)| (k.clone(), json(&v)))
+            .collect();
+        line.insert("problem_size".into(), serde_json::Value::Object(size));
+        let parameters: Map<String, serde_json::Value> = self.names
+            .iter()
+            .zip(values.iter())
+            .map(|(k, v)| (k.clone(), json(v)))
+            .collect();
+        line.insert("parameters".into(), serde_json::Value::Object(parameters));
+        match *status {
+            Status::Ok(ref times, time, ref kernel_limits) => {
+                line.insert("status".into(), json(&"ok"));
+                line.insert("time".into(), json(&seconds(time)));
+                line.insert("stats".into(), json(&TimeStats::new(times)));
+                for (k, x) in results.metric_names().iter().zip(results.metrics(time)) {
+                    line.insert(k.to_string(), json(&x));
+                }
+                line.insert("kernel_limits".into(), json(kernel_limits));
+            }
+            Status::Skipped(ref reason) => {
+                line.insert("status".into(), json(&"skipped"));
+                line.insert("reason".into(), json(reason));
+            }
+            Status::Failed(ref reason) => {
+                line.insert("status".into(), json(&"failed"));
+                line.insert("reason".into(), json(reason));
+            }
+        }
+        if let Some((global_size, local_size)) = work_sizes {
+            line.insert("global_size".into(), json(&global_size));
+            line.insert("local_size".into(), json(&local_size));
+        }
+        line.insert("device".into(), json(&results.fingerprint));
+        serde_json::Value::Object(line)
+    }
+}
+
+/// Quotes a CSV field if needed.
+fn csv_field(s: &str) -> String {
+    if s.contains(',') || s.contains('"') || s.contains('\n') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.into()
+    }
+}
+
+/// Saves the results of one or more devices as a JSON document.
+pub fn save(file: &str, results: &[TuningResults]) -> Result<(), String> {
+    let f = File::create(file).map_err(|e| format!("Could not create '{}': {}", file, e))?;
+    serde_json::to_writer_pretty(f, results).map_err(|e| e.to_string())
+}
+
+/// Loads results saved with `save`.
+pub fn load(file: &str) -> Result<Vec<TuningResults>, String> {
+    let f = File::open(file).map_err(|e| format!("Could not open '{}': {}", file, e))?;
+    serde_json::from_reader(f).map_err(|e| e.to_string())
+}
+
+pub fn print_header(names: &[&str], metrics: &[&str]) {
+    for k in names {
+        if k.len() > 8 {
+            print!("|{:^8}", &k[0..8]);
+        } else {
+            print!("|{:^8}", k);
+        }
+    }
+    print!("|{:^13}|", "Time(s.ns)");
+    for k in metrics {
+        print!("{:^8}|", k);
+    }
+    println!();
+    let l = 9 * names.len() + 15 + 9 * metrics.len();
+    println!("{}", (0..l).map(|_| "-").collect::<String>());
+}
+
+pub fn print_row(values: &[Value], time: Duration, metrics: &[f64]) {
+    for value in values {
+        print!("|{:>8}", value);
+    }
+    print!("|{:>3}.{:<09}|", time.as_secs(), time.subsec_nanos());
+    for x in metrics {
+        print!("{:>8.1}|", x);
+    }
+    println!();
+}
+
+/// A duration in seconds.
+pub fn seconds(time: Duration) -> f64 {
+    time.as_secs() as f64 + time.subsec_nanos() as f64 * 1e-9
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn results() -> TuningResults {
+        let mut results = TuningResults::new(DeviceFingerprint::dummy(),
+                                             "kernel".into(),
+                                             vec![("M".into(), 64)]);
+        results.flops = Some(2_000_000);
+        results
+    }
+
+    fn ok(micros: &[u64]) -> Status {
+        let times: Vec<Duration> = micros.iter().map(|&t| Duration::from_micros(t)).collect();
+        let mean = times.iter().sum::<Duration>() / times.len() as u32;
+        Status::Ok(times, mean, KernelLimits::dummy())
+    }
+
+    fn text(log: Log<Vec<u8>>) -> String {
+        String::from_utf8(log.file).unwrap()
+    }
+
+    #[test]
+    fn percentages_need_a_supplied_peak() {
+        let mut results = results();
+        results.bytes = Some(4_000_000);
+        assert_eq!(results.metric_names(), vec!["GFLOPS", "GB/s"]);
+        assert_eq!(results.metrics(Duration::from_millis(1)), vec![2.0, 4.0]);
+
+        results.peak_gflops = Some(8.0);
+        results.peak_bandwidth = Some(16.0);
+        assert_eq!(results.metric_names(), vec!["GFLOPS", "%peak", "GB/s", "%bw"]);
+        assert_eq!(results.metrics(Duration::from_millis(1)), vec![2.0, 25.0, 4.0, 25.0]);
+    }
+
+    #[test]
+    fn fingerprint_is_saved_with_the_results() {
+        let results = results();
+        let json = serde_json::to_string(&[&results]).unwrap();
+        let loaded: Vec<TuningResults> = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded[0].fingerprint, DeviceFingerprint::dummy());
+
+        let mut metadata = Vec::new();
+        results.write_metadata(&mut metadata).unwrap();
+        let metadata = String::from_utf8(metadata).unwrap();
+        assert!(metadata.contains("# device: Device\n"));
+        assert!(metadata.contains("# driver_version: 1.0\n"));
+        assert!(metadata.contains("# max_work_group_size: 256\n"));
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn csv_log_has_full_names_and_numeric_times() {
+        let names = ["A_VERY_LONG_PARAMETER_NAME", "LAYOUT"];
+        let mut log = Log::new(Vec::new(), LogFormat::Csv, &results(), &names).unwrap();
+        let values = [Value::Int(16), Value::Str("row,major".into())];
+        log.write(&results(), &values, &ok(&[100, 300]), None).unwrap();
+        log.write(&results(), &values, &Status::Skipped("Too large.".into()), None)
+            .unwrap();
+        log.write(&results(), &values, &Status::Failed("Build failed.".into()), None)
+            .unwrap();
+        let text = text(log);
+        let lines: Vec<&str> = text.lines().filter(|l| !l.starts_with('#')).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], "A_VERY_LONG_PARAMETER_NAME,LAYOUT,time,GFLOPS");
+        assert!(lines[1].starts_with("16,\"row,major\","));
+        let time: f64 = lines[1].split(',').nth(3).unwrap().parse().unwrap();
+        assert!((time - 0.0002).abs() < 1e-12);
+    }
+
+    #[test]
+    fn json_log_has_one_line_per_status() {
+        let mut log = Log::new(Vec::new(), LogFormat::JsonLines, &results(), &["X"]).unwrap();
+        let values = [Value::Int(1)];
+        log.write(&results(), &values, &ok(&[100, 300]), Some((vec![64], vec![8]))).unwrap();
+        log.write(&results(), &values, &Status::Skipped("Too large.".into()), None)
+            .unwrap();
+        log.write(&results(), &values, &Status::Failed("Build failed.".into()), None)
+            .unwrap();
+        let lines: Vec<serde_json::Value> = text(log)
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let statuses: Vec<&str> = lines.iter().map(|l| l["status"].as_str().unwrap()).collect();
+        assert_eq!(statuses, vec!["ok", "skipped", "failed"]);
+        assert_eq!(lines[0]["time"].as_f64(), Some(0.0002));
+        assert_eq!(lines[0]["stats"]["runs"].as_u64(), Some(2));
+        assert_eq!(lines[0]["parameters"]["X"], serde_json::json!(1));
+        assert_eq!(lines[0]["local_size"], serde_json::json!([8]));
+        assert_eq!(lines[1]["reason"].as_str(), Some("Too large."));
+    }
+
+    #[test]
+    fn time_stats() {
+        let stats = TimeStats::new(&[Duration::from_secs(3),
+                                     Duration::from_secs(1),
+                                     Duration::from_secs(2),
+                                     Duration::from_secs(6)]);
+        assert_eq!(stats.runs, 4);
+        assert_eq!((stats.min, stats.max, stats.mean, stats.median), (1.0, 6.0, 3.0, 2.5));
+        assert!((stats.std - 3.5f64.sqrt()).abs() < 1e-12);
+        assert_eq!(TimeStats::new(&[Duration::from_secs(2)]).median, 2.0);
+    }
+}
@@ -1,8 +1,8 @@
-use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
 
 use core::*;
+use data::Access;
 use ocl::SpatialDims;
 
 pub fn build_kernel_wrapper(file: &str, m: usize, n: usize) -> KernelWrapper {
@@ -13,18 +13,26 @@ pub fn build_kernel_wrapper(file: &str, m: usize, n: usize) -> KernelWrapper {
         .unwrap();
     KernelWrapper {
         scalar_inputs: vec![],
-        inputs_dims: vec![(m, n), (m, n), (m, n)],
-        src: src,
+        inputs_dims: vec![vec![m, n], vec![m, n], vec![m, n]],
+        inputs_padding: Vec::new(),
+        inputs_init: Vec::new(),
+        inputs_access: vec![Access::Output, Access::Input, Access::Input],
+        src,
         name: "add".into(),
+        problem_size: vec![("M".into(), m), ("N".into(), n)],
         ref_name: None,
         global_base: SpatialDims::Two(m, n),
         local_base: SpatialDims::Two(1, 1),
+        args: None,
+        images: Vec::new(),
+        flops: Some((m * n) as u64),
+        bytes: Some((4 * 3 * m * n) as u64),
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct SimpleBuilder {
-    parameters: HashMap<String, Vec<i32>>,
+    builder: ParameterSetBuilder,
 }
 
 impl Default for SimpleBuilder {
@@ -35,49 +43,24 @@ impl Default for SimpleBuilder {
 
 impl SimpleBuilder {
     pub fn new() -> Self {
-        SimpleBuilder { parameters: HashMap::new() }
+        let builder = ParameterSetBuilder::new()
+            .param::<i32>("VALUE1", vec![])
+            .param::<i32>("VALUE2", vec![])
+            .constraint("VALUE2 % VALUE1 == 0");
+        SimpleBuilder { builder }
     }
 
     pub fn value1(mut self, values: Vec<i32>) -> Self {
-        self.parameters.insert("VALUE1".into(), values);
+        self.builder = self.builder.param("VALUE1", values);
         self
     }
 
     pub fn value2(mut self, values: Vec<i32>) -> Self {
-        self.parameters.insert("VALUE2".into(), values);
+        self.builder = self.builder.param("VALUE2", values);
         self
     }
 
-    pub fn build<'a>(self) -> Result<ParameterSet<'a>, String> {
-        let ordered = vec!["VALUE1", "VALUE2"];
-        for &name in &ordered {
-            if self.parameters.get(name).is_none() {
-                return Err(format!("The Simple parameter set for '{}' has not been set.", name));
-            }
-        }
-        let parameters = ordered
-            .iter()
-            .map(move |&x| {
-                     let s: String = x.into();
-                     let v = self.parameters[&s].clone();
-                     (s, v)
-                 })
-            .collect();
-        let mut constraints: Vec<FnWrap<'static, bool>> = Vec::new();
-        fn multiple_of_x(v: &[i32]) -> bool {
-            v[1] % v[0] == 0
-        };
-        constraints.push(FnWrap {
-                             func: multiple_of_x,
-                             args: vec!["VALUE1", "VALUE2"],
-                         });
-        Ok(ParameterSet {
-               parameters: parameters,
-               constraints: constraints,
-               local_memory_needed: None,
-               mul_global_size: None,
-               mul_local_size: None,
-               div_global_size: None,
-           })
+    pub fn build(self) -> Result<ParameterSet, String> {
+        self.builder.build()
     }
 }
@@ -0,0 +1,659 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use ocl::SpatialDims;
+use serde_json;
+use toml;
+
+use args::{Arg, Scalar};
+use core::*;
+use data::{Access, Init};
+use expr::Expr;
+use image::{ImageArg, ImageFormat, SamplerArg};
+use values;
+
+/// A declarative description of a kernel and its tuning space, loaded from TOML or JSON.
+///
+/// Sizes are named constants such as `M`, `N` and `K`. Scalars, buffer dimensions and base
+/// work sizes are expressions over the sizes, while constraints, the local memory formula and
+/// work size rules are expressions over both the sizes and the parameters.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Spec {
+    pub kernel: String,
+    /// Path to the kernel source, relative to the spec file.
+    pub source: String,
+    #[serde(default)]
+    pub reference: Option<String>,
+    #[serde(default)]
+    pub sizes: Vec<SizeSpec>,
+    #[serde(default)]
+    pub scalars: Vec<String>,
+    #[serde(default)]
+    pub buffers: Vec<BufferSpec>,
+    #[serde(default)]
+    pub images: Vec<ImageSpec>,
+    pub parameters: Vec<ParameterSpec>,
+    #[serde(default)]
+    pub derived: Vec<DerivedSpec>,
+    #[serde(default)]
+    pub constraints: Vec<String>,
+    #[serde(default)]
+    pub local_memory: Option<String>,
+    pub work_size: WorkSizeSpec,
+    /// The floating point operations and bytes moved by one launch as expressions over the
+    /// sizes, e.g. `2 * M * N * K`, to report GFLOPS and bandwidth.
+    #[serde(default)]
+    pub flops: Option<String>,
+    #[serde(default)]
+    pub bytes: Option<String>,
+    /// The kernel arguments in order, instead of all scalars as `int`s followed by all
+    /// buffers: `buffer:<name>`, `dim:<buffer>:<dimension>` for the padded size of a
+    /// dimension of a buffer as an `int`, `<type>:<value>` for scalars, e.g. `uint:M * N` or
+    /// `float4:1, 0, 0, 1`, `local:<bytes>` with an expression over the parameters, `null`,
+    /// `image:<name>`, or `sampler` with optional options, e.g. `sampler:linear, repeat`.
+    #[serde(default)]
+    pub args: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SizeSpec {
+    pub name: String,
+    pub value: usize,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BufferSpec {
+    pub name: String,
+    /// Must be `float`, the only element type of the tuner's buffers. Other types are
+    /// rejected rather than converted; pass them as scalars in `args` instead.
+    #[serde(rename = "type", default = "default_buffer_type")]
+    pub data_type: String,
+    /// Expressions over the sizes, any number of them.
+    pub dims: Vec<String>,
+    /// The padded size of every dimension as an expression over the sizes and parameters,
+    /// e.g. `round_up(M, MWG)`. Padding is filled with zeros.
+    #[serde(default)]
+    pub padding: Option<Vec<String>>,
+    /// `uniform`, `uniform:<low>:<high>`, `normal`, `normal:<mean>:<std>`,
+    /// `constant:<value>`, `zeros` or `sequential`, see `data::Init`.
+    #[serde(default)]
+    pub init: Option<String>,
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// `input`, `output` or `inout`, see `data::Access`. Required, since inputs are read-only
+    /// for the kernel.
+    #[serde(default)]
+    pub access: Option<String>,
+}
+
+/// An image, only passed to the kernel through `image:<name>` in `args`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ImageSpec {
+    pub name: String,
+    /// Width, height and optionally depth as expressions over the sizes.
+    pub dims: Vec<String>,
+    /// E.g. `RGBA_FLOAT`, see `image::ImageFormat`.
+    #[serde(default = "default_image_format")]
+    pub format: String,
+    /// A string parameter whose values are formats, overriding `format`.
+    #[serde(default)]
+    pub format_param: Option<String>,
+    /// Required for the integer formats.
+    #[serde(default)]
+    pub init: Option<String>,
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// `input` by default, or `output` or `inout` for images the kernel writes.
+    #[serde(default)]
+    pub access: Option<String>,
+}
+
+/// The values of a parameter are the explicit `values` followed by those of any generators:
+/// `range = [start, end]` or `[start, end, step]`, `powers_of_two = [min, max]` and
+/// `divisors = { of = "K", min = 1, max = 64 }` for the divisors of a size expression.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ParameterSpec {
+    pub name: String,
+    /// Integers, booleans or strings, e.g. `[true, false]` or `["float", "half"]`.
+    #[serde(default)]
+    pub values: Vec<Value>,
+    #[serde(default)]
+    pub range: Option<Vec<i32>>,
+    #[serde(default)]
+    pub powers_of_two: Option<(i32, i32)>,
+    #[serde(default)]
+    pub divisors: Option<DivisorsSpec>,
+    /// Only searched over when this expression over the previous parameters holds, see
+    /// `ParameterSet::conditions`.
+    #[serde(default)]
+    pub active_if: Option<String>,
+    /// The value when inactive, the first value by default.
+    #[serde(default)]
+    pub default: Option<Value>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct DivisorsSpec {
+    pub of: String,
+    #[serde(default = "default_min_divisor")]
+    pub min: i32,
+    pub max: i32,
+}
+
+/// A parameter computed from the tuned ones by an expression, see `ParameterSet::derived`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct DerivedSpec {
+    pub name: String,
+    pub value: String,
+}
+
+/// Work sizes are `global_base` and `local_base` (all ones by default) adjusted either by the
+/// `mul_global`, `mul_local` and `div_global` parameter lists, with an empty string for no
+/// parameter, or replaced by the `global` and `local` expressions.
+#[derive(Clone, Debug, Deserialize)]
+pub struct WorkSizeSpec {
+    pub global_base: Vec<String>,
+    #[serde(default)]
+    pub local_base: Option<Vec<String>>,
+    #[serde(default)]
+    pub mul_global: Option<Vec<String>>,
+    #[serde(default)]
+    pub mul_local: Option<Vec<String>>,
+    #[serde(default)]
+    pub div_global: Option<Vec<String>>,
+    #[serde(default)]
+    pub global: Option<Vec<String>>,
+    #[serde(default)]
+    pub local: Option<Vec<String>>,
+}
+
+fn default_buffer_type() -> String {
+    "float".into()
+}
+
+fn default_min_divisor() -> i32 {
+    1
+}
+
+fn default_image_format() -> String {
+    "RGBA_FLOAT".into()
+}
+
+/// Loads a spec file, choosing the format from the `.toml` or `.json` extension, and builds
+/// the kernel wrapper and parameter set from it.
+pub fn load(file: &str) -> Result<(KernelWrapper, ParameterSet), String> {
+    let spec = Spec::from_file(file)?;
+    let dir = Path::new(file).parent().unwrap_or(Path::new(""));
+    spec.build(dir)
+}
+
+impl Spec {
+    pub fn from_file(file: &str) -> Result<Self, String> {
+        let mut src = String::new();
+        File::open(file)
+            .and_then(|mut f| f.read_to_string(&mut src))
+            .map_err(|e| format!("Could not read '{}': {}", file, e))?;
+        match Path::new(file).extension().and_then(|e| e.to_str()) {
+            Some("toml") => Spec::from_toml(&src),
+            Some("json") => Spec::from_json(&src),
+            _ => Err(format!("Unknown spec format for '{}', expected .toml or .json.", file)),
+        }
+    }
+
+    pub fn from_toml(src: &str) -> Result<Self, String> {
+        toml::from_str(src).map_err(|e| e.to_string())
+    }
+
+    pub fn from_json(src: &str) -> Result<Self, String> {
+        serde_json::from_str(src).map_err(|e| e.to_string())
+    }
+
+    /// Overrides the value of a named size.
+    pub fn set_size(&mut self, name: &str, value: usize) -> Result<(), String> {
+        match self.sizes.iter_mut().find(|s| s.name == name) {
+            Some(size) => {
+                size.value = value;
+                Ok(())
+            }
+            None => Err(format!("Size {} does not exist.", name)),
+        }
+    }
+
+    /// Builds the kernel wrapper and parameter set, reading the source relative to `dir`.
+    pub fn build(&self, dir: &Path) -> Result<(KernelWrapper, ParameterSet), String> {
+        let mut src = String::new();
+        let source = dir.join(&self.source);
+        File::open(&source)
+            .and_then(|mut f| f.read_to_string(&mut src))
+            .map_err(|e| format!("Could not read '{}': {}", source.display(), e))?;
+        let sizes: Vec<(String, usize)> = self.sizes
+            .iter()
+            .map(|s| (s.name.clone(), s.value))
+            .collect();
+
+        let mut scalar_inputs = Vec::new();
+        for (i, s) in self.scalars.iter().enumerate() {
+            let field = format!("scalars[{}]", i);
+            let x = self.size_expr(&field, s, &sizes)?;
+            if x > i32::MAX as usize {
+                return Err(format!("{}: The value {} does not fit in an int.", field, x));
+            }
+            scalar_inputs.push(x as i32);
+        }
+        let mut inputs_dims = Vec::new();
+        let mut inputs_init = Vec::new();
+        let mut inputs_access = Vec::new();
+        for buffer in &self.buffers {
+            if buffer.data_type != "float" {
+                return Err(format!("Buffer {} has type '{}', but buffers only hold 'float's. \
+                                    Pass values of other types as scalars in 'args'.",
+                                   buffer.name,
+                                   buffer.data_type));
+            }
+            let dims = buffer
+                .dims
+                .iter()
+                .enumerate()
+                .map(|(i, d)| {
+                         self.size_expr(&format!("Buffer {} dims[{}]", buffer.name, i), d, &sizes)
+                     })
+                .collect::<Result<Vec<_>, _>>()?;
+            if dims.is_empty() {
+                return Err(format!("Buffer {} must have at least one dimension.", buffer.name));
+            }
+            inputs_dims.push(dims);
+            let mut init = match buffer.init {
+                Some(ref init) => Init::from_str(init)?,
+                None => Init::default(),
+            };
+            if let Some(seed) = buffer.seed {
+                init = init.seed(seed);
+            }
+            inputs_init.push(init);
+            let access = buffer
+                .access
+                .as_ref()
+                .ok_or_else(|| {
+                                format!("Buffer {} needs an access: 'input', 'output' or 'inout'.",
+                                        buffer.name)
+                            })?;
+            inputs_access.push(Access::from_str(access)?);
+        }
+        let global_base = self.work_size
+            .global_base
+            .iter()
+            .enumerate()
+            .map(|(i, d)| self.size_expr(&format!("global_base[{}]", i), d, &sizes))
+            .collect::<Result<Vec<_>, _>>()?;
+        let local_base = match self.work_size.local_base {
+            Some(ref local_base) => {
+                local_base
+                    .iter()
+                    .enumerate()
+                    .map(|(i, d)| self.size_expr(&format!("local_base[{}]", i), d, &sizes))
+                    .collect::<Result<Vec<_>, _>>()?
+            }
+            None => vec![1; global_base.len()],
+        };
+        let mut wrapper = KernelWrapper {
+            scalar_inputs,
+            inputs_dims,
+            inputs_padding: Vec::new(),
+            inputs_init,
+            inputs_access,
+            src,
+            name: self.kernel.clone(),
+            problem_size: sizes.clone(),
+            ref_name: self.reference.clone(),
+            global_base: spatial_dims(&global_base)?,
+            local_base: spatial_dims(&local_base)?,
+            args: None,
+            images: Vec::new(),
+            flops: None,
+            bytes: None,
+        };
+        if let Some(ref flops) = self.flops {
+            wrapper.flops = Some(self.size_expr("flops", flops, &sizes)? as u64);
+        }
+        if let Some(ref bytes) = self.bytes {
+            wrapper.bytes = Some(self.size_expr("bytes", bytes, &sizes)? as u64);
+        }
+        for image in &self.images {
+            let dims = image
+                .dims
+                .iter()
+                .enumerate()
+                .map(|(i, d)| {
+                         self.size_expr(&format!("Image {} dims[{}]", image.name, i), d, &sizes)
+                     })
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut arg = ImageArg::new(dims, ImageFormat::from_str(&image.format)?);
+            arg.format_param = image.format_param.clone();
+            if let Some(ref init) = image.init {
+                arg.init = Some(Init::from_str(init)?);
+            }
+            if let Some(seed) = image.seed {
+                arg.init = Some(arg.init.unwrap_or_default().seed(seed));
+            }
+            if let Some(ref access) = image.access {
+                arg.access = Access::from_str(access)?;
+            }
+            wrapper.images.push(arg);
+        }
+
+        // Sizes are substituted before the expressions reach the builder, which validates
+        // them against the parameters. Expressions which do not parse are passed on as they
+        // are, so that the builder reports them together with the other problems.
+        let substitute = |src: &str| -> String {
+            Expr::parse(src)
+                .map(|e| e.substitute(&sizes).to_string())
+                .unwrap_or_else(|_| src.to_string())
+        };
+        let mut builder = ParameterSetBuilder::new();
+        for (i, p) in self.parameters.iter().enumerate() {
+            if self.parameters[..i].iter().any(|q| q.name == p.name) {
+                return Err(format!("Parameter {} is declared more than once.", p.name));
+            }
+            builder = builder.param(&p.name, self.parameter_values(p, &sizes)?);
+            if let Some(ref src) = p.active_if {
+                builder = match p.default {
+                    Some(ref default) => {
+                        builder.condition_default(&p.name, &substitute(src), default.clone())
+                    }
+                    None => builder.condition(&p.name, &substitute(src)),
+                };
+            }
+        }
+        for d in &self.derived {
+            builder = builder.derived(&d.name, &substitute(&d.value));
+        }
+        for c in &self.constraints {
+            builder = builder.constraint(&substitute(c));
+        }
+        if let Some(ref src) = self.local_memory {
+            builder = builder.local_memory(&substitute(src));
+        }
+        if let Some(list) = Spec::parameter_list(&self.work_size.mul_local) {
+            builder = builder.mul_local_size(list);
+        }
+        if let Some(list) = Spec::parameter_list(&self.work_size.mul_global) {
+            builder = builder.mul_global_size(list);
+        }
+        if let Some(list) = Spec::parameter_list(&self.work_size.div_global) {
+            builder = builder.div_global_size(list);
+        }
+        if let Some(ref exprs) = self.work_size.global {
+            let exprs = exprs.iter().map(|e| substitute(e)).collect::<Vec<_>>();
+            builder = builder.global_size(exprs.iter().map(|e| &e[..]).collect());
+        }
+        if let Some(ref exprs) = self.work_size.local {
+            let exprs = exprs.iter().map(|e| substitute(e)).collect::<Vec<_>>();
+            builder = builder.local_size(exprs.iter().map(|e| &e[..]).collect());
+        }
+        let mut params = builder.build()?;
+        let named_all = |field: &str, sizes: Option<Vec<WorkSizeFn>>| {
+            sizes.map(|sizes| {
+                          sizes.into_iter()
+                              .enumerate()
+                              .map(|(i, size)| named(size, format!("{}[{}]", field, i)))
+                              .collect()
+                      })
+        };
+        params.global_size = named_all("global", params.global_size.take());
+        params.local_size = named_all("local", params.local_size.take());
+
+        let names = params.all_names();
+        for buffer in &self.buffers {
+            let mut padding: Vec<WorkSizeFn> = Vec::new();
+            for (i, p) in buffer.padding.iter().flat_map(|p| p.iter()).enumerate() {
+                let size = self.parameter_expr(p, &sizes, &names)?.into_work_size();
+                padding.push(named(size, format!("Buffer {} padding[{}]", buffer.name, i)));
+            }
+            wrapper.inputs_padding.push(padding);
+        }
+        if let Some(ref args) = self.args {
+            let mut list = Vec::new();
+            for arg in args {
+                list.push(self.arg(arg, &sizes, &names)?);
+            }
+            wrapper.args = Some(list);
+        }
+        params.validate(Some(&wrapper))?;
+        Ok((wrapper, params))
+    }
+
+    /// The explicit and generated values of a parameter.
+    fn parameter_values(&self, p: &ParameterSpec, sizes: &[(String, usize)])
+                        -> Result<Vec<Value>, String> {
+        let mut generated = Vec::new();
+        if let Some(ref range) = p.range {
+            match range.len() {
+                2 => generated.extend(values::range(range[0], range[1], 1)?),
+                3 if range[2] > 0 => generated.extend(values::range(range[0], range[1], range[2])?),
+                _ => {
+                    return Err(format!("The range of {} must be [start, end] or [start, end, \
+                                        step] with a positive step.",
+                                       p.name))
+                }
+            }
+        }
+        if let Some((min, max)) = p.powers_of_two {
+            generated.extend(values::powers_of_two(min, max));
+        }
+        if let Some(ref d) = p.divisors {
+            let of = self.size_expr(&format!("The divisors of {}", p.name), &d.of, sizes)?;
+            generated.extend(values::divisors(of, d.min, d.max));
+        }
+        let mut values = p.values.clone();
+        values.extend(generated.into_iter().map(Value::Int));
+        if values.is_empty() {
+            return Err(format!("Parameter {} has no values.", p.name));
+        }
+        Ok(values)
+    }
+
+    fn arg(&self, src: &str, sizes: &[(String, usize)], names: &[&str]) -> Result<Arg, String> {
+        match src.trim() {
+            "null" => return Ok(Arg::Null),
+            "sampler" => return Ok(Arg::Sampler(SamplerArg::default())),
+            _ => {}
+        }
+        let i = src.find(':').ok_or_else(|| format!("Invalid argument '{}'.", src))?;
+        let (kind, value) = (src[..i].trim(), src[i + 1..].trim());
+        match kind {
+            "buffer" => {
+                self.buffers
+                    .iter()
+                    .position(|b| b.name == value)
+                    .map(Arg::Buffer)
+                    .ok_or_else(|| format!("Buffer {} does not exist.", value))
+            }
+            "dim" => {
+                let j = value
+                    .rfind(':')
+                    .ok_or_else(|| format!("Invalid argument '{}', expected dim:<buffer>:<dim>.",
+                                           src))?;
+                let (name, dim) = (value[..j].trim(), value[j + 1..].trim());
+                let buffer = self.buffers
+                    .iter()
+                    .position(|b| b.name == name)
+                    .ok_or_else(|| format!("Buffer {} does not exist.", name))?;
+                let dim = dim.parse()
+                    .map_err(|_| format!("Invalid dimension '{}' in '{}'.", dim, src))?;
+                Ok(Arg::Dim(buffer, dim))
+            }
+            "image" => {
+                self.images
+                    .iter()
+                    .position(|image| image.name == value)
+                    .map(Arg::Image)
+                    .ok_or_else(|| format!("Image {} does not exist.", value))
+            }
+            "sampler" => Ok(Arg::Sampler(SamplerArg::from_str(value)?)),
+            "local" => Ok(Arg::Local(self.parameter_expr(value, sizes, names)?.into_formula())),
+            "char" | "uchar" | "short" | "ushort" | "int" | "uint" | "long" | "ulong" => {
+                let expr = Expr::parse(value)?.substitute(sizes);
+                expr.check_names(&[])?;
+                let value = expr.eval(&Config::new())?;
+                Ok(Arg::Scalar(Scalar::parse(kind, &value.to_string())?))
+            }
+            _ => Ok(Arg::Scalar(Scalar::parse(kind, value)?)),
+        }
+    }
+
+    /// Evaluates an expression which may only refer to the sizes and must not be negative,
+    /// with errors naming the field.
+    fn size_expr(&self, field: &str, src: &str, sizes: &[(String, usize)])
+                 -> Result<usize, String> {
+        Expr::parse(src)
+            .and_then(|expr| {
+                          let expr = expr.substitute(sizes);
+                          expr.check_names(&[])?;
+                          expr.eval_size(&Config::new())
+                      })
+            .map_err(|e| format!("{}: {}", field, e))
+    }
+
+    /// Parses an expression over the sizes and parameters, substituting the sizes.
+    fn parameter_expr(&self, src: &str, sizes: &[(String, usize)], names: &[&str])
+                      -> Result<Expr, String> {
+        let expr = Expr::parse(src)?.substitute(sizes);
+        expr.check_names(names)?;
+        Ok(expr)
+    }
+
+    fn parameter_list(list: &Option<Vec<String>>) -> Option<Vec<Option<&str>>> {
+        list.as_ref().map(|list| {
+                              list.iter()
+                                  .map(|x| if x.is_empty() { None } else { Some(&x[..]) })
+                                  .collect()
+                          })
+    }
+}
+
+fn spatial_dims(dims: &[usize]) -> Result<SpatialDims, String> {
+    match dims.len() {
+        1 => Ok(SpatialDims::One(dims[0])),
+        2 => Ok(SpatialDims::Two(dims[0], dims[1])),
+        3 => Ok(SpatialDims::Three(dims[0], dims[1], dims[2])),
+        _ => Err("Work sizes must have 1, 2 or 3 dimensions.".into()),
+    }
+}
+
+/// Prefixes the errors of a work size with the field it comes from.
+fn named(size: WorkSizeFn, field: String) -> WorkSizeFn {
+    Arc::new(move |base, config: &Config| {
+                 size(base, config).map_err(|e| format!("{}: {}", field, e))
+             })
+}
+
+#[cfg(test)]
+mod tests {
+    use ocl::enums::FilterMode;
+
+    use super::*;
+
+    fn template(name: &str) -> String {
+        format!("{}/templates/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    /// Builds a small spec for `simple.ocl` with the given buffer dimensions and arguments.
+    fn build(dims: &str, args: &str) -> Result<(KernelWrapper, ParameterSet), String> {
+        let src = format!(r#"
+            kernel = "add"
+            source = "simple.ocl"
+            sizes = [{{ name = "M", value = 64 }}]
+            args = [{}]
+            [[buffers]]
+            name = "A"
+            dims = [{}]
+            padding = ["round_up(M, WGS)", "M"]
+            access = "inout"
+            [[parameters]]
+            name = "WGS"
+            values = [8, 16]
+            [work_size]
+            global_base = ["M"]
+            mul_local = ["WGS"]
+        "#,
+                          args,
+                          dims);
+        Spec::from_toml(&src)?.build(Path::new(&template("")))
+    }
+
+    #[test]
+    fn templates_load() {
+        let (wrapper, params) = load(&template("gemm.toml")).unwrap();
+        assert_eq!(wrapper.name, "gemm_fast");
+        assert_eq!(wrapper.inputs_dims, vec![vec![2048, 2048]; 3]);
+        assert_eq!(params.parameters.len(), 17);
+        assert_eq!(params.derived.len(), 8);
+        assert_eq!(params.conditions.len(), 3);
+        assert_eq!(params.parameters[0].0, "MWG");
+
+        let (wrapper, params) = load(&template("simple.json")).unwrap();
+        assert_eq!(wrapper.name, "add");
+        assert_eq!(wrapper.inputs_access, vec![Access::Output, Access::Input, Access::Input]);
+        assert_eq!(params.parameters.len(), 2);
+        assert_eq!(wrapper.bytes, Some(12 * 1024 * 1024));
+    }
+
+    #[test]
+    fn invalid_sizes_name_the_field() {
+        let e = build(r#""M - 65", "M""#, "").err().unwrap();
+        assert!(e.starts_with("Buffer A dims[0]: "), "{}", e);
+        let e = build(r#""M", """#, "").err().unwrap();
+        assert!(e.starts_with("Buffer A dims[1]: "), "{}", e);
+    }
+
+    #[test]
+    fn args_are_parsed_in_order() {
+        let args = r#""buffer:A", "dim:A:0", "uint:M * 2", "float4:1, 0, 0, 1", "local:WGS * 4",
+                      "null", "sampler:linear, repeat""#;
+        let (wrapper, params) = build(r#""M", "M""#, args).unwrap();
+        let args = wrapper.args.unwrap();
+        assert_eq!(args.len(), 7);
+        match (&args[0], &args[1], &args[2], &args[3]) {
+            (&Arg::Buffer(0),
+             &Arg::Dim(0, 0),
+             &Arg::Scalar(Scalar::UInt(128)),
+             &Arg::Scalar(Scalar::Float4([1.0, 0.0, 0.0, 1.0]))) => {}
+            other => panic!("Unexpected arguments {:?}", other),
+        }
+        match args[4] {
+            Arg::Local(ref bytes) => assert_eq!(bytes.call(&params.config(&[1]).unwrap()), Ok(64)),
+            ref other => panic!("Unexpected argument {:?}", other),
+        }
+        assert!(matches!(args[5], Arg::Null));
+        match args[6] {
+            Arg::Sampler(SamplerArg { filter_mode: FilterMode::Linear, .. }) => {}
+            ref other => panic!("Unexpected argument {:?}", other),
+        }
+        assert!(build(r#""M", "M""#, r#""buffer:B""#).is_err());
+        assert!(build(r#""M", "M""#, r#""dim:A""#).is_err());
+    }
+
+    #[test]
+    fn only_float_buffers_are_accepted() {
+        let mut spec = Spec::from_json(&::std::fs::read_to_string(template("simple.json"))
+                                            .unwrap())
+                .unwrap();
+        spec.buffers[1].data_type = "int".into();
+        let e = spec.build(Path::new(&template(""))).err().unwrap();
+        assert!(e.starts_with("Buffer m1 has type 'int'"), "{}", e);
+    }
+
+    #[test]
+    fn parameter_problems_are_reported_together() {
+        let mut spec = Spec::from_json(&::std::fs::read_to_string(template("simple.json"))
+                                            .unwrap())
+                .unwrap();
+        spec.constraints = vec!["VALUE1 >".into()];
+        spec.local_memory = Some("4 * (VALUE2".into());
+        let e = spec.build(Path::new(&template(""))).err().unwrap();
+        assert_eq!(e.lines().count(), 2, "{}", e);
+    }
+}
@@ -0,0 +1,628 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use core::{Tuner, KernelWrapper, ParameterSet, Value};
+use multi::indexed_log_file;
+use results::{seconds, TuningResult, TuningResults};
+
+/// A problem size as named sizes, e.g. `[("M", 512), ("N", 512), ("K", 64)]`.
+pub type ProblemSize = Vec<(String, usize)>;
+
+/// The parameter values of a configuration, as in `TuningResult::parameters`.
+pub type Parameters = [(String, Value)];
+
+/// All combinations of the given values of each size, the last size changing fastest.
+pub fn grid(sizes: &[(&str, Vec<usize>)]) -> Vec<ProblemSize> {
+    let mut grid = vec![Vec::new()];
+    for &(name, ref values) in sizes {
+        grid = grid.into_iter()
+            .flat_map(|size: ProblemSize| {
+                values.iter().map(move |&v| {
+                                      let mut size = size.clone();
+                                      size.push((name.to_string(), v));
+                                      size
+                                  })
+            })
+            .collect();
+    }
+    grid
+}
+
+/// The most sets of candidates `SweepResults::select` searches exhaustively before falling
+/// back to the greedy choice.
+const MAX_EXACT_SEARCH: u64 = 100_000;
+
+/// The slowdown of a time relative to the best time for its size. A best time of zero, e.g.
+/// from a timer too coarse for the kernel, only matches another time of zero.
+fn slowdown(time: f64, best: f64) -> f64 {
+    if best > 0.0 {
+        time / best
+    } else if time > 0.0 {
+        f64::INFINITY
+    } else {
+        1.0
+    }
+}
+
+/// The number of ways to choose `k` of `n` items, none when `k > n`, saturating at
+/// `u64::MAX`.
+fn combinations(n: usize, k: usize) -> u64 {
+    if k > n {
+        return 0;
+    }
+    let mut c: u64 = 1;
+    for i in 0..k.min(n - k) {
+        c = match c.checked_mul((n - i) as u64) {
+            Some(c) => c / (i as u64 + 1),
+            None => return u64::MAX,
+        };
+    }
+    c
+}
+
+/// How the times of one configuration across problem sizes are combined into one score.
+///
+/// Each time is first divided by the best time for its size, so that large sizes do not
+/// dominate. The score is then the mean or the maximum of these slowdowns.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aggregate {
+    Mean,
+    Worst,
+}
+
+impl FromStr for Aggregate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mean" => Ok(Aggregate::Mean),
+            "worst" => Ok(Aggregate::Worst),
+            _ => Err(format!("Unknown aggregate '{}', expected 'mean' or 'worst'.", s)),
+        }
+    }
+}
+
+impl fmt::Display for Aggregate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Aggregate::Mean => f.pad("mean"),
+            Aggregate::Worst => f.pad("worst"),
+        }
+    }
+}
+
+/// The results of tuning one kernel for several problem sizes, in the order of the sizes.
+#[derive(Clone, Debug)]
+pub struct SweepResults {
+    pub results: Vec<TuningResults>,
+}
+
+impl SweepResults {
+    /// The best configuration for each problem size, if any was valid.
+    pub fn best_per_size(&self) -> Vec<(&ProblemSize, Option<&TuningResult>)> {
+        self.results
+            .iter()
+            .map(|r| (&r.problem_size, r.best()))
+            .collect()
+    }
+
+    /// The time of every configuration which is valid for all problem sizes, in the order of
+    /// the sizes.
+    pub fn common_times(&self) -> Vec<(&Parameters, Vec<Duration>)> {
+        let mut times: HashMap<&Parameters, Vec<Duration>> = HashMap::new();
+        let mut order = Vec::new();
+        for (i, results) in self.results.iter().enumerate() {
+            for result in &results.results {
+                let key = &result.parameters[..];
+                if i == 0 {
+                    order.push(key);
+                    times.insert(key, vec![result.time]);
+                } else if let Some(t) = times.get_mut(key) {
+                    if t.len() == i {
+                        t.push(result.time);
+                    }
+                }
+            }
+        }
+        order.into_iter()
+            .filter_map(|key| {
+                            let t = times.remove(key).unwrap();
+                            if t.len() == self.results.len() {
+                                Some((key, t))
+                            } else {
+                                None
+                            }
+                        })
+            .collect()
+    }
+
+    /// The single configuration which is best across all problem sizes, with its score, see
+    /// `Aggregate`. Configurations which failed for any size are not considered.
+    pub fn best_overall(&self, aggregate: Aggregate) -> Option<(Vec<(String, Value)>, f64)> {
+        let best = self.results
+            .iter()
+            .map(|r| r.best().map(|b| seconds(b.time)))
+            .collect::<Option<Vec<_>>>()?;
+        self.common_times()
+            .into_iter()
+            .map(|(parameters, times)| {
+                let slowdowns = times
+                    .iter()
+                    .zip(best.iter())
+                    .map(|(&t, &b)| slowdown(seconds(t), b));
+                let score = match aggregate {
+                    Aggregate::Mean => slowdowns.sum::<f64>() / best.len() as f64,
+                    Aggregate::Worst => slowdowns.fold(0.0, f64::max),
+                };
+                (parameters, score)
+            })
+            .fold(None, |best: Option<(&Parameters, f64)>, (parameters, score)| {
+                match best {
+                    Some((_, s)) if s <= score => best,
+                    _ => Some((parameters, score)),
+                }
+            })
+            .map(|(parameters, score)| (parameters.to_vec(), score))
+    }
+
+    /// Chooses at most `k` configurations such that every problem size has one within
+    /// `tolerance` of its best time, e.g. `0.1` for 10%, see `Selection`.
+    ///
+    /// The smallest such set is found by an exhaustive search over the configurations which
+    /// are not dominated by another one. If no set of `k` covers every size, or there are too
+    /// many candidates to search them all, the `k` configurations covering the most sizes are
+    /// chosen greedily instead.
+    pub fn select(&self, k: usize, tolerance: f64) -> Selection {
+        let best: Vec<Option<f64>> = self.results
+            .iter()
+            .map(|r| r.best().map(|b| seconds(b.time)))
+            .collect();
+        // The time of every configuration for every size, in order of first appearance
+        let mut times: Vec<(&Parameters, Vec<Option<f64>>)> = Vec::new();
+        let mut index: HashMap<&Parameters, usize> = HashMap::new();
+        for (i, results) in self.results.iter().enumerate() {
+            for result in &results.results {
+                let key = &result.parameters[..];
+                let j = *index
+                     .entry(key)
+                     .or_insert_with(|| {
+                                         times.push((key, vec![None; best.len()]));
+                                         times.len() - 1
+                                     });
+                times[j].1[i] = Some(seconds(result.time));
+            }
+        }
+        let covers = |t: &[Option<f64>]| -> Vec<bool> {
+            t.iter()
+                .zip(best.iter())
+                .map(|(&t, &b)| match (t, b) {
+                         (Some(t), Some(b)) => t <= b * (1.0 + tolerance),
+                         _ => false,
+                     })
+                .collect()
+        };
+        let mean_slowdown = |t: &[Option<f64>]| -> f64 {
+            let slowdowns: Vec<f64> = t.iter()
+                .zip(best.iter())
+                .filter_map(|(&t, &b)| match (t, b) {
+                                (Some(t), Some(b)) => Some(slowdown(t, b)),
+                                _ => None,
+                            })
+                .collect();
+            slowdowns.iter().sum::<f64>() / slowdowns.len() as f64
+        };
+
+        // Candidates with distinct coverage, keeping the lowest mean slowdown for each, and
+        // without those covering a subset of another candidate
+        let mut candidates: Vec<(usize, Vec<bool>, f64)> = Vec::new();
+        for (j, (_, t)) in times.iter().enumerate() {
+            let (coverage, slowdown) = (covers(t), mean_slowdown(t));
+            if !coverage.iter().any(|&c| c) {
+                continue;
+            }
+            match candidates.iter().position(|c| c.1 == coverage) {
+                Some(p) if candidates[p].2 > slowdown => candidates[p] = (j, coverage, slowdown),
+                Some(_) => {}
+                None => candidates.push((j, coverage, slowdown)),
+            }
+        }
+        let subset = |a: &[bool], b: &[bool]| a.iter().zip(b.iter()).all(|(&a, &b)| !a || b);
+        let dominated: Vec<bool> = candidates
+            .iter()
+            .map(|a| {
+                     candidates
+                         .iter()
+                         .any(|b| a.1 != b.1 && subset(&a.1, &b.1))
+                 })
+            .collect();
+        let candidates: Vec<_> = candidates
+            .into_iter()
+            .zip(dominated)
+            .filter(|&(_, d)| !d)
+            .map(|(c, _)| c)
+            .collect();
+
+        let coverable: Vec<bool> = (0..best.len())
+            .map(|i| candidates.iter().any(|c| c.1[i]))
+            .collect();
+        let searches = (0..=k).fold(0u64, |s, n| {
+            s.saturating_add(combinations(candidates.len(), n))
+        });
+        let mut chosen = Vec::new();
+        let exact = coverable.iter().all(|&c| c) && searches <= MAX_EXACT_SEARCH &&
+                    (0..=k).any(|n| cover(&candidates, &mut vec![false; best.len()], n,
+                                          &mut chosen));
+        if !exact {
+            chosen.clear();
+            let mut covered = vec![false; best.len()];
+            while chosen.len() < k {
+                let next = (0..candidates.len())
+                    .filter(|c| !chosen.contains(c))
+                    .map(|c| {
+                             let n = candidates[c]
+                                 .1
+                                 .iter()
+                                 .zip(covered.iter())
+                                 .filter(|&(&a, &b)| a && !b)
+                                 .count();
+                             (c, n)
+                         })
+                    .filter(|&(_, n)| n > 0)
+                    .max_by_key(|&(_, n)| n);
+                match next {
+                    Some((c, _)) => {
+                        for (covered, &a) in covered.iter_mut().zip(candidates[c].1.iter()) {
+                            *covered = *covered || a;
+                        }
+                        chosen.push(c);
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        let configurations: Vec<Vec<(String, Value)>> = chosen
+            .iter()
+            .map(|&c| times[candidates[c].0].0.to_vec())
+            .collect();
+        let dispatch = self.results
+            .iter()
+            .enumerate()
+            .map(|(i, results)| {
+                let choice = chosen
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(n, &c)| {
+                                    times[candidates[c].0].1[i]
+                                        .map(|t| (n, slowdown(t, best[i].unwrap())))
+                                })
+                    .fold(None, |best: Option<(usize, f64)>, (n, s)| match best {
+                        Some((_, b)) if b <= s => best,
+                        _ => Some((n, s)),
+                    });
+                (results.problem_size.clone(), choice)
+            })
+            .collect();
+        Selection {
+            tolerance,
+            configurations,
+            dispatch,
+        }
+    }
+
+    /// Prints the best configuration for each size and the best one across all sizes.
+    pub fn print(&self, aggregate: Aggregate) {
+        for (size, best) in self.best_per_size() {
+            let size: Vec<String> = size.iter().map(|&(ref k, v)| format!("{}={}", k, v)).collect();
+            match best {
+                Some(best) => {
+                    println!("{}: best time {}.{:09}s with {:?}",
+                             size.join(" "),
+                             best.time.as_secs(),
+                             best.time.subsec_nanos(),
+                             best.parameters)
+                }
+                None => println!("{}: no valid configuration.", size.join(" ")),
+            }
+        }
+        match self.best_overall(aggregate) {
+            Some((parameters, score)) => {
+                println!("Best across all sizes ({} slowdown {:.3}): {:?}",
+                         aggregate,
+                         score,
+                         parameters)
+            }
+            None => println!("No configuration is valid for all sizes."),
+        }
+    }
+}
+
+/// A small set of configurations for a range of problem sizes, see `SweepResults::select`.
+#[derive(Clone, Debug)]
+pub struct Selection {
+    pub tolerance: f64,
+    pub configurations: Vec<Vec<(String, Value)>>,
+    /// For every problem size, the index of the fastest chosen configuration and its slowdown
+    /// relative to the best time for the size, or `None` if no chosen configuration is valid.
+    pub dispatch: Vec<(ProblemSize, Option<(usize, f64)>)>,
+}
+
+impl Selection {
+    /// The problem sizes with no chosen configuration within the tolerance.
+    pub fn uncovered(&self) -> Vec<&ProblemSize> {
+        self.dispatch
+            .iter()
+            .filter(|&&(_, choice)| choice.is_none_or(|(_, s)| s > 1.0 + self.tolerance))
+            .map(|(size, _)| size)
+            .collect()
+    }
+
+    /// A dispatch table from problem sizes to configurations, one entry per size in the form
+    /// `{ { {"M", 256}, ... }, { {"MWG", 64}, {"LAYOUT", "row"}, ... } },`, with string values
+    /// quoted.
+    pub fn dispatch_table(&self) -> String {
+        let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+        let pair = |k: &str, v: String| format!("{{{}, {}}}", quote(k), v);
+        self.dispatch
+            .iter()
+            .filter_map(|&(ref size, choice)| choice.map(|(n, _)| (size, n)))
+            .map(|(size, n)| {
+                let size: Vec<String> = size.iter()
+                    .map(|&(ref k, v)| pair(k, v.to_string()))
+                    .collect();
+                let values: Vec<String> = self.configurations[n]
+                    .iter()
+                    .map(|(k, v)| match *v {
+                             Value::Str(ref s) => pair(k, quote(s)),
+                             _ => pair(k, v.define()),
+                         })
+                    .collect();
+                format!("{{ {{ {} }}, {{ {} }} }},\n", size.join(", "), values.join(", "))
+            })
+            .collect()
+    }
+
+    /// Prints the chosen configurations, the one used for each size and its slowdown.
+    pub fn print(&self) {
+        for (n, configuration) in self.configurations.iter().enumerate() {
+            println!("Configuration {}: {:?}", n, configuration);
+        }
+        for &(ref size, choice) in &self.dispatch {
+            let size: Vec<String> = size.iter().map(|&(ref k, v)| format!("{}={}", k, v)).collect();
+            match choice {
+                Some((n, slowdown)) => {
+                    println!("{}: configuration {}, slowdown {:.3}", size.join(" "), n, slowdown)
+                }
+                None => println!("{}: no valid configuration.", size.join(" ")),
+            }
+        }
+        let uncovered = self.uncovered();
+        if !uncovered.is_empty() {
+            println!("{} sizes are not within {}% of their best time.",
+                     uncovered.len(),
+                     self.tolerance * 100.0);
+        }
+    }
+}
+
+/// Searches for `n` candidates which together cover every size, branching on the candidates
+/// covering the first uncovered size.
+fn cover(candidates: &[(usize, Vec<bool>, f64)], covered: &mut Vec<bool>, n: usize,
+         chosen: &mut Vec<usize>)
+         -> bool {
+    let first = match covered.iter().position(|&c| !c) {
+        Some(first) => first,
+        None => return true,
+    };
+    if n == 0 {
+        return false;
+    }
+    for (c, candidate) in candidates.iter().enumerate() {
+        if !candidate.1[first] {
+            continue;
+        }
+        let previous = covered.clone();
+        for (covered, &a) in covered.iter_mut().zip(candidate.1.iter()) {
+            *covered = *covered || a;
+        }
+        chosen.push(c);
+        if cover(candidates, covered, n - 1, chosen) {
+            return true;
+        }
+        chosen.pop();
+        *covered = previous;
+    }
+    false
+}
+
+/// Tunes a kernel for each problem size in turn, with the wrapper and parameter set built for
+/// each size by `build`, so that buffers, scalars and size dependent parameter values follow
+/// the size. When a log file is given, each size writes to its own file with the index of the
+/// size appended to the file stem.
+pub fn tune<F>(tuner: &Tuner, sizes: &[ProblemSize], build: F, runs: usize,
+               log_file: Option<&str>)
+               -> Result<SweepResults, String>
+    where F: Fn(&[(String, usize)]) -> Result<(KernelWrapper, ParameterSet), String>
+{
+    let mut results = Vec::new();
+    for (i, size) in sizes.iter().enumerate() {
+        let (wrapper, params) = build(size)?;
+        let log_file = log_file.map(|f| indexed_log_file(f, i));
+        results.push(tuner.tune(wrapper, params, runs, log_file.as_ref().map(|f| &f[..]))?);
+    }
+    Ok(SweepResults { results })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use device::{DeviceFingerprint, KernelLimits};
+    use results::TuningResult;
+
+    /// Results for one size from the configurations `X=<x>` with their times in microseconds.
+    fn results(m: usize, times: &[(i32, u64)]) -> TuningResults {
+        let mut results = TuningResults::new(DeviceFingerprint::dummy(),
+                                             "kernel".into(),
+                                             vec![("M".into(), m)]);
+        results.results = times
+            .iter()
+            .map(|&(x, t)| {
+                     TuningResult {
+                         parameters: vec![("X".into(), Value::Int(x))],
+                         derived: Vec::new(),
+                         time: Duration::from_micros(t),
+                         kernel_limits: KernelLimits::dummy(),
+                     }
+                 })
+            .collect();
+        results
+    }
+
+    fn x(parameters: &Parameters) -> i32 {
+        match parameters[0].1 {
+            Value::Int(x) => x,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn grid_changes_the_last_size_fastest() {
+        let grid = grid(&[("M", vec![1, 2]), ("N", vec![3, 4])]);
+        let pairs: Vec<(usize, usize)> = grid.iter().map(|s| (s[0].1, s[1].1)).collect();
+        assert_eq!(pairs, vec![(1, 3), (1, 4), (2, 3), (2, 4)]);
+        assert!(grid.iter().all(|s| s[0].0 == "M" && s[1].0 == "N"));
+    }
+
+    #[test]
+    fn best_per_size_follows_the_sizes() {
+        let sweep = SweepResults {
+            results: vec![results(1, &[(1, 10), (2, 5)]),
+                          results(2, &[]),
+                          results(3, &[(1, 30), (2, 40)])],
+        };
+        let best: Vec<(usize, Option<i32>)> = sweep
+            .best_per_size()
+            .into_iter()
+            .map(|(size, best)| (size[0].1, best.map(|b| x(&b.parameters))))
+            .collect();
+        assert_eq!(best, vec![(1, Some(2)), (2, None), (3, Some(1))]);
+    }
+
+    #[test]
+    fn aggregates_round_trip() {
+        for &aggregate in &[Aggregate::Mean, Aggregate::Worst] {
+            assert_eq!(Aggregate::from_str(&aggregate.to_string()), Ok(aggregate));
+        }
+        assert!(Aggregate::from_str("median").is_err());
+    }
+
+    #[test]
+    fn common_times_skip_configurations_missing_for_a_size() {
+        let sweep = SweepResults {
+            results: vec![results(1, &[(1, 10), (2, 20), (3, 30)]),
+                          results(2, &[(3, 35), (1, 15)])],
+        };
+        let common: Vec<(i32, Vec<Duration>)> = sweep
+            .common_times()
+            .into_iter()
+            .map(|(p, t)| (x(p), t))
+            .collect();
+        assert_eq!(common,
+                   vec![(1, vec![Duration::from_micros(10), Duration::from_micros(15)]),
+                        (3, vec![Duration::from_micros(30), Duration::from_micros(35)])]);
+    }
+
+    #[test]
+    fn best_overall_by_mean_and_worst_slowdown() {
+        // X=1 is best for the first size and X=2 for the second, X=3 is never far behind
+        let sweep = SweepResults {
+            results: vec![results(1, &[(1, 10), (2, 40), (3, 13)]),
+                          results(2, &[(1, 40), (2, 10), (3, 13)])],
+        };
+        let (parameters, score) = sweep.best_overall(Aggregate::Mean).unwrap();
+        assert_eq!(x(&parameters), 3);
+        assert!((score - 1.3).abs() < 1e-9);
+        let (parameters, score) = sweep.best_overall(Aggregate::Worst).unwrap();
+        assert_eq!(x(&parameters), 3);
+        assert!((score - 1.3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn best_overall_with_a_zero_best_time() {
+        let sweep = SweepResults {
+            results: vec![results(1, &[(1, 0), (2, 10)]), results(2, &[(1, 10), (2, 10)])],
+        };
+        let (parameters, score) = sweep.best_overall(Aggregate::Worst).unwrap();
+        assert_eq!(x(&parameters), 1);
+        assert_eq!(score, 1.0);
+        assert!(sweep.select(1, 0.0).uncovered().is_empty());
+    }
+
+    #[test]
+    fn select_finds_the_smallest_cover() {
+        // Greedy would first take X=1, which covers three sizes, and then need two more
+        let sweep = SweepResults {
+            results: vec![results(1, &[(1, 10), (2, 10), (3, 20)]),
+                          results(2, &[(1, 10), (2, 10), (3, 20)]),
+                          results(3, &[(1, 10), (2, 20), (3, 10)]),
+                          results(4, &[(1, 20), (2, 20), (3, 10)]),
+                          results(5, &[(1, 20), (2, 10), (3, 20), (4, 10)]),
+                          results(6, &[(1, 20), (2, 20), (3, 10), (4, 10)])],
+        };
+        let selection = sweep.select(3, 0.0);
+        let chosen: Vec<i32> = selection.configurations.iter().map(|p| x(p)).collect();
+        assert_eq!(chosen, vec![2, 3]);
+        assert!(selection.uncovered().is_empty());
+        let dispatch: Vec<usize> = selection
+            .dispatch
+            .iter()
+            .map(|&(_, choice)| choice.unwrap().0)
+            .collect();
+        assert_eq!(dispatch, vec![0, 0, 1, 1, 0, 1]);
+    }
+
+    #[test]
+    fn select_falls_back_to_greedy_and_reports_uncovered_sizes() {
+        let sweep = SweepResults {
+            results: vec![results(1, &[(1, 10), (2, 20), (3, 20)]),
+                          results(2, &[(1, 10), (2, 20), (3, 20)]),
+                          results(3, &[(1, 20), (2, 10), (3, 20)]),
+                          results(4, &[(1, 20), (2, 20), (3, 10)])],
+        };
+        let selection = sweep.select(2, 0.0);
+        let chosen: Vec<i32> = selection.configurations.iter().map(|p| x(p)).collect();
+        assert_eq!(chosen[0], 1);
+        assert_eq!(chosen.len(), 2);
+        let uncovered: Vec<usize> = selection.uncovered().iter().map(|s| s[0].1).collect();
+        assert_eq!(uncovered.len(), 1);
+        assert!(uncovered[0] == 3 || uncovered[0] == 4);
+    }
+
+    #[test]
+    fn combinations_saturate() {
+        assert_eq!(combinations(5, 2), 10);
+        assert_eq!(combinations(5, 3), 10);
+        assert_eq!(combinations(5, 5), 1);
+        assert_eq!(combinations(5, 0), 1);
+        assert_eq!(combinations(2, 5), 0);
+        assert_eq!(combinations(0, 1), 0);
+        assert_eq!(combinations(1000, 500), u64::MAX);
+    }
+
+    #[test]
+    fn dispatch_table_quotes_strings() {
+        let selection = Selection {
+            tolerance: 0.0,
+            configurations: vec![vec![("X".into(), Value::Int(2)),
+                                      ("LAYOUT".into(), Value::Str("a\"b".into())),
+                                      ("FAST".into(), Value::Bool(true))]],
+            dispatch: vec![(vec![("M".into(), 256)], Some((0, 1.0))),
+                           (vec![("M".into(), 512)], None)],
+        };
+        assert_eq!(selection.dispatch_table(),
+                   concat!("{ { {\"M\", 256} }, ",
+                           "{ {\"X\", 2}, {\"LAYOUT\", \"a\\\"b\"}, {\"FAST\", 1} } },\n"));
+    }
+}
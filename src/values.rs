@@ -0,0 +1,110 @@
+use ocl::Device;
+use ocl::enums::{DeviceInfo, DeviceInfoResult};
+
+/// The values from `start` to `end` inclusive, with the given step, which must be positive.
+pub fn range(start: i32, end: i32, step: i32) -> Result<Vec<i32>, String> {
+    if step <= 0 {
+        return Err(format!("The step of a range must be positive, not {}.", step));
+    }
+    Ok(steps(start, end, step))
+}
+
+/// All powers of two between `min` and `max` inclusive.
+pub fn powers_of_two(min: i32, max: i32) -> Vec<i32> {
+    let mut values = Vec::new();
+    let mut value = Some(1i32);
+    while let Some(v) = value.filter(|&v| v <= max) {
+        if v >= min {
+            values.push(v);
+        }
+        value = v.checked_mul(2);
+    }
+    values
+}
+
+/// All divisors of `n`, e.g. a problem size, between `min` and `max` inclusive.
+pub fn divisors(n: usize, min: i32, max: i32) -> Vec<i32> {
+    let max = max.min(n.min(i32::MAX as usize) as i32);
+    (min.max(1)..=max)
+        .filter(|&d| n.is_multiple_of(d as usize))
+        .collect()
+}
+
+/// The multiples of `base`, which must be positive, up to `max` inclusive.
+pub fn multiples(base: i32, max: i32) -> Result<Vec<i32>, String> {
+    if base <= 0 {
+        return Err(format!("The base of multiples must be positive, not {}.", base));
+    }
+    Ok(steps(base, max, base))
+}
+
+/// The values from `start` to `end` inclusive, stopping before `i32::MAX` would overflow.
+fn steps(start: i32, end: i32, step: i32) -> Vec<i32> {
+    let mut values = Vec::new();
+    let mut value = Some(start);
+    while let Some(v) = value.filter(|&v| v <= end) {
+        values.push(v);
+        value = v.checked_add(step);
+    }
+    values
+}
+
+/// The preferred vector width for `float` reported by the device.
+pub fn preferred_vector_width(device: &Device) -> i32 {
+    match device.info(DeviceInfo::PreferredVectorWidthFloat) {
+        DeviceInfoResult::PreferredVectorWidthFloat(x) => (x as i32).max(1),
+        _ => unreachable!(),
+    }
+}
+
+/// The preferred `float` vector width of the device times the powers of two, up to `max`
+/// inclusive and at most 16, the widest OpenCL vector, e.g. for `VWM` and `VWN`.
+pub fn vector_widths(device: &Device, max: i32) -> Vec<i32> {
+    widths(preferred_vector_width(device), max)
+}
+
+fn widths(preferred: i32, max: i32) -> Vec<i32> {
+    let mut values = Vec::new();
+    let mut value = preferred;
+    while value <= max.min(16) {
+        values.push(value);
+        value *= 2;
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generators_stop_at_the_largest_int() {
+        assert_eq!(range(i32::MAX - 1, i32::MAX, 1).unwrap(), vec![i32::MAX - 1, i32::MAX]);
+        assert_eq!(powers_of_two(1 << 29, i32::MAX), vec![1 << 29, 1 << 30]);
+        assert_eq!(divisors(12, i32::MAX - 1, i32::MAX), Vec::<i32>::new());
+        assert_eq!(multiples(i32::MAX, i32::MAX).unwrap(), vec![i32::MAX]);
+        assert_eq!(divisors(12, 0, 6), vec![1, 2, 3, 4, 6]);
+    }
+
+    #[test]
+    fn divisors_stop_at_the_number() {
+        assert_eq!(divisors(64, 1, i32::MAX), vec![1, 2, 4, 8, 16, 32, 64]);
+        assert_eq!(divisors(7, 2, i32::MAX), vec![7]);
+        assert_eq!(divisors(0, 1, i32::MAX), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn vector_widths_are_powers_of_two_up_to_16() {
+        assert_eq!(widths(1, 8), vec![1, 2, 4, 8]);
+        assert_eq!(widths(4, i32::MAX), vec![4, 8, 16]);
+        assert_eq!(widths(2, 7), vec![2, 4]);
+        assert_eq!(widths(16, 8), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn non_positive_steps_are_errors() {
+        assert!(range(0, 4, 0).is_err());
+        assert!(range(0, 4, -1).is_err());
+        assert!(multiples(0, 4).is_err());
+    }
+}